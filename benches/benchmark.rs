@@ -1,5 +1,6 @@
 use criterion::{Criterion, criterion_group, criterion_main};
-use std::hint::black_box;
+use std::{hint::black_box, path::Path};
+use tempfile::TempDir;
 
 trait SearchPathHelper {
     fn simple(path: impl Into<String>) -> SearchPath {
@@ -10,7 +11,13 @@ trait SearchPathHelper {
         SearchPath::Complex {
             path: path.into(),
             depth,
-            show_hidden: Some(true),
+            min_depth: None,
+            show_hidden: Some(ShowHidden::All),
+            preview: None,
+            weight: None,
+            session_options: None,
+            exclude_regex: None,
+            env: None,
         }
         .expand()
         .unwrap()
@@ -18,33 +25,102 @@ trait SearchPathHelper {
 }
 impl SearchPathHelper for SearchPath {}
 
-use tmux_sessionizer::config::{Config, SearchPath, Settings};
+use tmux_sessionizer::{
+    config::{Config, SearchPath, Settings, ShowHidden},
+    discovery::{self, WalkOptions},
+};
+
+/// Builds a tree in `path` where every directory has `breadth` children, `depth` levels deep
+fn build(path: &Path, breadth: usize, depth: u8) {
+    if depth == 0 {
+        return;
+    }
+
+    for i in 0..breadth {
+        let child = path.join(format!("dir_{i}"));
+        std::fs::create_dir_all(&child).unwrap();
+        build(&child, breadth, depth - 1);
+    }
+}
+
+fn build_tree(breadth: usize, depth: u8) -> TempDir {
+    let dir = tempfile::tempdir().unwrap();
+    build(dir.path(), breadth, depth);
+    dir
+}
 
 fn find_all_dirs(c: &mut Criterion) {
+    // Shaped like a home directory, so the numbers don't depend on the machine
+    let home = tempfile::tempdir().unwrap();
+    let root = |p: &str| home.path().join(p).to_str().unwrap().to_string();
+    build(&home.path().join("Code"), 6, 3);
+    build(&home.path().join("Documents/Work"), 4, 2);
+    build(&home.path().join(".config"), 10, 1);
+    build(&home.path().join("vaults"), 3, 1);
+
     let config = Config {
         paths: vec![
-            SearchPath::simple("~/Code"),
-            SearchPath::simple("~/Documents/Work"),
-            SearchPath::complex("~/Documents", Some(1)),
-            SearchPath::complex("~/.config", Some(1)),
-            SearchPath::complex("~/vaults", Some(0)),
+            SearchPath::simple(root("Code")),
+            SearchPath::simple(root("Documents/Work")),
+            SearchPath::complex(root("Documents"), Some(1)),
+            SearchPath::complex(root(".config"), Some(1)),
+            SearchPath::complex(root("vaults"), Some(0)),
         ],
         settings: Settings {
             default_depth: 8,
-            picker: None,
+            ..Default::default()
         },
     };
 
     c.bench_function("find_dirs", |b| {
-        b.iter(|| black_box(config.find_dirs().unwrap()));
+        b.iter(|| black_box(discovery::find_dirs(&config).unwrap()));
     });
 }
 
+fn find_dir_recursive(c: &mut Criterion) {
+    let mut group = c.benchmark_group("find_dir_recursive");
+
+    let shallow_wide = build_tree(40, 2);
+    group.bench_function("shallow_wide", |b| {
+        b.iter(|| {
+            black_box(discovery::find_dir_recursive(
+                &WalkOptions::default(),
+                shallow_wide.path(),
+                1,
+                2,
+            ))
+        });
+    });
+
+    let deep_narrow = build_tree(2, 10);
+    group.bench_function("deep_narrow", |b| {
+        b.iter(|| {
+            black_box(discovery::find_dir_recursive(
+                &WalkOptions::default(),
+                deep_narrow.path(),
+                1,
+                10,
+            ))
+        });
+    });
+
+    group.finish();
+}
+
 fn read_config(c: &mut Criterion) {
+    let dir = tempfile::tempdir().unwrap();
+    let config = dir.path().join("tms.yml");
+    std::fs::write(
+        &config,
+        "settings:\n  default_depth: 8\npaths:\n  - ~/Code\n  - path: ~/Documents\n    depth: 1\n",
+    )
+    .unwrap();
+    let layers = [config];
+
     c.bench_function("read_config", |b| {
-        b.iter(|| black_box(Config::try_open()));
+        b.iter(|| black_box(Config::load_layered(&layers).unwrap()));
     });
 }
 
-criterion_group!(benches, find_all_dirs, read_config);
+criterion_group!(benches, find_all_dirs, find_dir_recursive, read_config);
 criterion_main!(benches);
@@ -1,8 +1,11 @@
 use crate::{
-    config::SearchPath,
+    config::{InsideAction, LayoutWindow, Mode, NameReplace, NameSource, SearchPath, ShowHidden},
     error::{CacheError, Error},
 };
-use std::io::{Read, Write};
+use std::{
+    collections::BTreeMap,
+    io::{Read, Write},
+};
 
 fn write<W: Write>(writer: &mut W, elem_type: &'static str, data: &[u8]) -> Result<(), Error> {
     writer
@@ -21,6 +24,30 @@ fn read_n<R: Read, const N: usize>(
     }
 }
 
+/// Prefixes the cache file, bump the version when the layout changes
+const CACHE_MAGIC: &[u8; 3] = b"TMS";
+const CACHE_VERSION: u8 = 53;
+
+pub fn write_cache_header<W: Write>(writer: &mut W) -> Result<(), Error> {
+    write(writer, "Cache magic", CACHE_MAGIC)?;
+    write(writer, "Cache version", &[CACHE_VERSION])
+}
+
+/// Fails if the cache was written by a different version
+pub fn read_cache_header<R: Read>(reader: &mut R) -> Result<(), Error> {
+    let magic: [u8; 3] = read_n(reader, "Cache magic")?;
+    let version: [u8; 1] = read_n(reader, "Cache version")?;
+
+    if &magic != CACHE_MAGIC || version[0] != CACHE_VERSION {
+        return Err(Error::Cache(CacheError::Read(
+            "Cache version",
+            std::io::Error::other("Outdated cache file"),
+        )));
+    }
+
+    Ok(())
+}
+
 pub trait WriteBinary {
     fn write_binary<W: Write>(&self, writer: &mut W) -> Result<(), Error>;
 }
@@ -125,6 +152,44 @@ where
     }
 }
 
+impl<K, V> WriteBinary for BTreeMap<K, V>
+where
+    K: WriteBinary,
+    V: WriteBinary,
+{
+    fn write_binary<W: Write>(&self, writer: &mut W) -> Result<(), Error> {
+        let length = self.len();
+        write(writer, "Map length", &length.to_ne_bytes())?;
+        for (key, value) in self {
+            key.write_binary(writer)?;
+            value.write_binary(writer)?;
+        }
+
+        Ok(())
+    }
+}
+
+impl<K, V> ReadBinary for BTreeMap<K, V>
+where
+    K: ReadBinary + Ord,
+    V: ReadBinary,
+{
+    fn read_binary<R: Read>(reader: &mut R) -> Result<Self, Error> {
+        let length = {
+            let len = read_n(reader, "Map length")?;
+            usize::from_ne_bytes(len)
+        };
+
+        let mut map = BTreeMap::new();
+        for _ in 0..length {
+            let key = K::read_binary(reader)?;
+            map.insert(key, V::read_binary(reader)?);
+        }
+
+        Ok(map)
+    }
+}
+
 impl WriteBinary for u8 {
     fn write_binary<W: Write>(&self, writer: &mut W) -> Result<(), Error> {
         write(writer, "u8", &[*self])
@@ -137,6 +202,30 @@ impl ReadBinary for u8 {
     }
 }
 
+impl WriteBinary for i32 {
+    fn write_binary<W: Write>(&self, writer: &mut W) -> Result<(), Error> {
+        write(writer, "i32", &self.to_le_bytes())
+    }
+}
+
+impl ReadBinary for i32 {
+    fn read_binary<R: Read>(reader: &mut R) -> Result<Self, Error> {
+        read_n(reader, "i32").map(i32::from_le_bytes)
+    }
+}
+
+impl WriteBinary for u64 {
+    fn write_binary<W: Write>(&self, writer: &mut W) -> Result<(), Error> {
+        write(writer, "u64", &self.to_le_bytes())
+    }
+}
+
+impl ReadBinary for u64 {
+    fn read_binary<R: Read>(reader: &mut R) -> Result<Self, Error> {
+        read_n(reader, "u64").map(u64::from_le_bytes)
+    }
+}
+
 impl WriteBinary for bool {
     fn write_binary<W: Write>(&self, writer: &mut W) -> Result<(), Error> {
         let byte = match self {
@@ -158,6 +247,57 @@ impl WriteBinary for crate::config::Settings {
     fn write_binary<W: Write>(&self, writer: &mut W) -> Result<(), Error> {
         write(writer, "u8", &[self.default_depth])?;
         self.picker.write_binary(writer)?;
+        self.window_name.write_binary(writer)?;
+        self.include_files.write_binary(writer)?;
+        self.on_create.write_binary(writer)?;
+        self.source_command.write_binary(writer)?;
+        self.display_icons.write_binary(writer)?;
+        self.name_from.write_binary(writer)?;
+        self.terminal.write_binary(writer)?;
+        self.shell.write_binary(writer)?;
+        self.layout.write_binary(writer)?;
+        self.picker_extra_args.write_binary(writer)?;
+        self.skip_filesystems.write_binary(writer)?;
+        self.pinned.write_binary(writer)?;
+        self.new_root.write_binary(writer)?;
+        self.tmux_socket.write_binary(writer)?;
+        self.tmux_socket_path.write_binary(writer)?;
+        self.log_level.write_binary(writer)?;
+        self.inside_tmux_action.write_binary(writer)?;
+        self.group_by_root.write_binary(writer)?;
+        self.picker_null_delimited.write_binary(writer)?;
+        self.preselect_cwd.write_binary(writer)?;
+        self.follow_symlinks.write_binary(writer)?;
+        self.popup.write_binary(writer)?;
+        self.exclude_current.write_binary(writer)?;
+        self.name_replace.write_binary(writer)?;
+        self.shell_login.write_binary(writer)?;
+        self.name_from_realpath.write_binary(writer)?;
+        self.mode.write_binary(writer)?;
+        self.scratch_path.write_binary(writer)?;
+        self.group_labels.write_binary(writer)?;
+        self.max_depth_cap.write_binary(writer)?;
+        self.tmux_config.write_binary(writer)?;
+        self.strip_extension_for_name.write_binary(writer)?;
+        self.picker_header.write_binary(writer)?;
+        self.no_steal.write_binary(writer)?;
+        self.picker_exact.write_binary(writer)?;
+        self.auto_suffix.write_binary(writer)?;
+        self.bookmarks_dir.write_binary(writer)?;
+        self.use_direnv.write_binary(writer)?;
+        self.show_hidden.write_binary(writer)?;
+        self.lossy_paths.write_binary(writer)?;
+        self.picker_tmux_mode.write_binary(writer)?;
+        self.prefix_with_profile.write_binary(writer)?;
+        self.strict_roots.write_binary(writer)?;
+        self.collapse_single_child.write_binary(writer)?;
+        self.session_options.write_binary(writer)?;
+        self.owned_only.write_binary(writer)?;
+        self.on_create_delay_ms.write_binary(writer)?;
+        self.show_git_branch.write_binary(writer)?;
+        self.exclude_regex.write_binary(writer)?;
+        self.retry_on_cancel.write_binary(writer)?;
+        self.env.write_binary(writer)?;
 
         Ok(())
     }
@@ -167,14 +307,286 @@ impl ReadBinary for crate::config::Settings {
     fn read_binary<R: Read>(reader: &mut R) -> Result<Self, Error> {
         let default_depth: [u8; 1] = read_n(reader, "u8")?;
         let picker = Option::<String>::read_binary(reader)?;
+        let window_name = Option::<String>::read_binary(reader)?;
+        let include_files = Option::<Vec<String>>::read_binary(reader)?;
+        let on_create = Option::<String>::read_binary(reader)?;
+        let source_command = Option::<String>::read_binary(reader)?;
+        let display_icons = Option::<bool>::read_binary(reader)?;
+        let name_from = Option::<NameSource>::read_binary(reader)?;
+        let terminal = Option::<String>::read_binary(reader)?;
+        let shell = Option::<String>::read_binary(reader)?;
+        let layout = Option::<Vec<LayoutWindow>>::read_binary(reader)?;
+        let picker_extra_args = Option::<Vec<String>>::read_binary(reader)?;
+        let skip_filesystems = Option::<Vec<String>>::read_binary(reader)?;
+        let pinned = Option::<Vec<String>>::read_binary(reader)?;
+        let new_root = Option::<String>::read_binary(reader)?;
+        let tmux_socket = Option::<String>::read_binary(reader)?;
+        let tmux_socket_path = Option::<String>::read_binary(reader)?;
+        let log_level = Option::<String>::read_binary(reader)?;
+        let inside_tmux_action = Option::<InsideAction>::read_binary(reader)?;
+        let group_by_root = Option::<bool>::read_binary(reader)?;
+        let picker_null_delimited = Option::<bool>::read_binary(reader)?;
+        let preselect_cwd = Option::<bool>::read_binary(reader)?;
+        let follow_symlinks = Option::<bool>::read_binary(reader)?;
+        let popup = Option::<bool>::read_binary(reader)?;
+        let exclude_current = Option::<bool>::read_binary(reader)?;
+        let name_replace = Option::<Vec<NameReplace>>::read_binary(reader)?;
+        let shell_login = Option::<bool>::read_binary(reader)?;
+        let name_from_realpath = Option::<bool>::read_binary(reader)?;
+        let mode = Option::<Mode>::read_binary(reader)?;
+        let scratch_path = Option::<String>::read_binary(reader)?;
+        let group_labels = Option::<bool>::read_binary(reader)?;
+        let max_depth_cap = Option::<u8>::read_binary(reader)?;
+        let tmux_config = Option::<String>::read_binary(reader)?;
+        let strip_extension_for_name = Option::<bool>::read_binary(reader)?;
+        let picker_header = Option::<String>::read_binary(reader)?;
+        let no_steal = Option::<bool>::read_binary(reader)?;
+        let picker_exact = Option::<bool>::read_binary(reader)?;
+        let auto_suffix = Option::<bool>::read_binary(reader)?;
+        let bookmarks_dir = Option::<String>::read_binary(reader)?;
+        let use_direnv = Option::<bool>::read_binary(reader)?;
+        let show_hidden = Option::<ShowHidden>::read_binary(reader)?;
+        let lossy_paths = Option::<bool>::read_binary(reader)?;
+        let picker_tmux_mode = Option::<String>::read_binary(reader)?;
+        let prefix_with_profile = Option::<bool>::read_binary(reader)?;
+        let strict_roots = Option::<bool>::read_binary(reader)?;
+        let collapse_single_child = Option::<bool>::read_binary(reader)?;
+        let session_options = Option::<Vec<String>>::read_binary(reader)?;
+        let owned_only = Option::<bool>::read_binary(reader)?;
+        let on_create_delay_ms = Option::<u64>::read_binary(reader)?;
+        let show_git_branch = Option::<bool>::read_binary(reader)?;
+        let exclude_regex = Option::<Vec<String>>::read_binary(reader)?;
+        let retry_on_cancel = Option::<bool>::read_binary(reader)?;
+        let env = Option::<BTreeMap<String, String>>::read_binary(reader)?;
 
         Ok(crate::config::Settings {
             default_depth: default_depth[0],
             picker,
+            window_name,
+            include_files,
+            on_create,
+            source_command,
+            display_icons,
+            name_from,
+            terminal,
+            shell,
+            layout,
+            picker_extra_args,
+            skip_filesystems,
+            pinned,
+            new_root,
+            tmux_socket,
+            tmux_socket_path,
+            log_level,
+            inside_tmux_action,
+            group_by_root,
+            picker_null_delimited,
+            preselect_cwd,
+            follow_symlinks,
+            popup,
+            exclude_current,
+            name_replace,
+            shell_login,
+            name_from_realpath,
+            mode,
+            scratch_path,
+            group_labels,
+            max_depth_cap,
+            tmux_config,
+            strip_extension_for_name,
+            picker_header,
+            no_steal,
+            picker_exact,
+            auto_suffix,
+            bookmarks_dir,
+            use_direnv,
+            show_hidden,
+            lossy_paths,
+            picker_tmux_mode,
+            prefix_with_profile,
+            strict_roots,
+            collapse_single_child,
+            session_options,
+            owned_only,
+            on_create_delay_ms,
+            show_git_branch,
+            exclude_regex,
+            retry_on_cancel,
+            env,
+        })
+    }
+}
+
+mod name_source {
+    #![allow(non_upper_case_globals)]
+
+    pub const Dir: u8 = 0;
+    pub const Project: u8 = 1;
+}
+
+impl WriteBinary for NameSource {
+    fn write_binary<W: Write>(&self, writer: &mut W) -> Result<(), Error> {
+        let byte = match self {
+            NameSource::Dir => name_source::Dir,
+            NameSource::Project => name_source::Project,
+        };
+
+        write(writer, "NameSource type byte", &[byte])
+    }
+}
+
+impl ReadBinary for NameSource {
+    fn read_binary<R: Read>(reader: &mut R) -> Result<Self, Error> {
+        let byte: [u8; 1] = read_n(reader, "NameSource type byte")?;
+        match byte[0] {
+            name_source::Dir => Ok(NameSource::Dir),
+            name_source::Project => Ok(NameSource::Project),
+
+            x => Err(Error::Cache(CacheError::Read(
+                "NameSource type byte",
+                std::io::Error::other(format!("Invalid NameSource type byte: {x}")),
+            ))),
+        }
+    }
+}
+
+impl WriteBinary for LayoutWindow {
+    fn write_binary<W: Write>(&self, writer: &mut W) -> Result<(), Error> {
+        self.name.write_binary(writer)?;
+        self.command.write_binary(writer)
+    }
+}
+
+impl ReadBinary for LayoutWindow {
+    fn read_binary<R: Read>(reader: &mut R) -> Result<Self, Error> {
+        Ok(LayoutWindow {
+            name: String::read_binary(reader)?,
+            command: Option::<String>::read_binary(reader)?,
         })
     }
 }
 
+impl WriteBinary for NameReplace {
+    fn write_binary<W: Write>(&self, writer: &mut W) -> Result<(), Error> {
+        self.from.write_binary(writer)?;
+        self.to.write_binary(writer)
+    }
+}
+
+impl ReadBinary for NameReplace {
+    fn read_binary<R: Read>(reader: &mut R) -> Result<Self, Error> {
+        Ok(NameReplace {
+            from: String::read_binary(reader)?,
+            to: String::read_binary(reader)?,
+        })
+    }
+}
+
+mod show_hidden {
+    #![allow(non_upper_case_globals)]
+
+    pub const Off: u8 = 0;
+    pub const All: u8 = 1;
+    pub const Top: u8 = 2;
+}
+
+impl WriteBinary for ShowHidden {
+    fn write_binary<W: Write>(&self, writer: &mut W) -> Result<(), Error> {
+        let byte = match self {
+            ShowHidden::Off => show_hidden::Off,
+            ShowHidden::All => show_hidden::All,
+            ShowHidden::Top => show_hidden::Top,
+        };
+
+        write(writer, "ShowHidden type byte", &[byte])
+    }
+}
+
+impl ReadBinary for ShowHidden {
+    fn read_binary<R: Read>(reader: &mut R) -> Result<Self, Error> {
+        let byte: [u8; 1] = read_n(reader, "ShowHidden type byte")?;
+        match byte[0] {
+            show_hidden::Off => Ok(ShowHidden::Off),
+            show_hidden::All => Ok(ShowHidden::All),
+            show_hidden::Top => Ok(ShowHidden::Top),
+
+            x => Err(Error::Cache(CacheError::Read(
+                "ShowHidden type byte",
+                std::io::Error::other(format!("Invalid ShowHidden type byte: {x}")),
+            ))),
+        }
+    }
+}
+
+mod mode {
+    #![allow(non_upper_case_globals)]
+
+    pub const Session: u8 = 0;
+    pub const Window: u8 = 1;
+}
+
+impl WriteBinary for Mode {
+    fn write_binary<W: Write>(&self, writer: &mut W) -> Result<(), Error> {
+        let byte = match self {
+            Mode::Session => mode::Session,
+            Mode::Window => mode::Window,
+        };
+
+        write(writer, "Mode type byte", &[byte])
+    }
+}
+
+impl ReadBinary for Mode {
+    fn read_binary<R: Read>(reader: &mut R) -> Result<Self, Error> {
+        let byte: [u8; 1] = read_n(reader, "Mode type byte")?;
+        match byte[0] {
+            mode::Session => Ok(Mode::Session),
+            mode::Window => Ok(Mode::Window),
+
+            x => Err(Error::Cache(CacheError::Read(
+                "Mode type byte",
+                std::io::Error::other(format!("Invalid Mode type byte: {x}")),
+            ))),
+        }
+    }
+}
+
+mod inside_action {
+    #![allow(non_upper_case_globals)]
+
+    pub const Switch: u8 = 0;
+    pub const Attach: u8 = 1;
+    pub const NewWindowToSession: u8 = 2;
+}
+
+impl WriteBinary for InsideAction {
+    fn write_binary<W: Write>(&self, writer: &mut W) -> Result<(), Error> {
+        let byte = match self {
+            InsideAction::Switch => inside_action::Switch,
+            InsideAction::Attach => inside_action::Attach,
+            InsideAction::NewWindowToSession => inside_action::NewWindowToSession,
+        };
+
+        write(writer, "InsideAction type byte", &[byte])
+    }
+}
+
+impl ReadBinary for InsideAction {
+    fn read_binary<R: Read>(reader: &mut R) -> Result<Self, Error> {
+        let byte: [u8; 1] = read_n(reader, "InsideAction type byte")?;
+        match byte[0] {
+            inside_action::Switch => Ok(InsideAction::Switch),
+            inside_action::Attach => Ok(InsideAction::Attach),
+            inside_action::NewWindowToSession => Ok(InsideAction::NewWindowToSession),
+
+            x => Err(Error::Cache(CacheError::Read(
+                "InsideAction type byte",
+                std::io::Error::other(format!("Invalid InsideAction type byte: {x}")),
+            ))),
+        }
+    }
+}
+
 mod search_path {
     #![allow(non_upper_case_globals)]
 
@@ -196,7 +608,13 @@ impl WriteBinary for SearchPath {
             SearchPath::Complex {
                 path,
                 depth,
+                min_depth,
                 show_hidden,
+                preview,
+                weight,
+                session_options,
+                exclude_regex,
+                env,
             } => {
                 write(
                     writer,
@@ -205,7 +623,13 @@ impl WriteBinary for SearchPath {
                 )?;
                 path.write_binary(writer)?;
                 depth.write_binary(writer)?;
-                show_hidden.write_binary(writer)
+                min_depth.write_binary(writer)?;
+                show_hidden.write_binary(writer)?;
+                preview.write_binary(writer)?;
+                weight.write_binary(writer)?;
+                session_options.write_binary(writer)?;
+                exclude_regex.write_binary(writer)?;
+                env.write_binary(writer)
             }
         }
     }
@@ -219,7 +643,13 @@ impl ReadBinary for SearchPath {
             search_path::Complex => Ok(Self::Complex {
                 path: String::read_binary(reader)?,
                 depth: Option::<u8>::read_binary(reader)?,
-                show_hidden: Option::<bool>::read_binary(reader)?,
+                min_depth: Option::<u8>::read_binary(reader)?,
+                show_hidden: Option::<ShowHidden>::read_binary(reader)?,
+                preview: Option::<String>::read_binary(reader)?,
+                weight: Option::<i32>::read_binary(reader)?,
+                session_options: Option::<Vec<String>>::read_binary(reader)?,
+                exclude_regex: Option::<Vec<String>>::read_binary(reader)?,
+                env: Option::<BTreeMap<String, String>>::read_binary(reader)?,
             }),
 
             x => Err(Error::Cache(CacheError::Read(
@@ -1,17 +1,361 @@
-use crate::binary::{ReadBinary, WriteBinary};
+use crate::binary::{ReadBinary, WriteBinary, read_cache_header, write_cache_header};
 use crate::error::{CacheError, Error};
-use rayon::prelude::*;
+use regex::Regex;
 use serde::{Deserialize, Serialize};
+use serde_yml::{Mapping, Value};
 use std::{
-    fs::{DirEntry, File},
-    os::unix::ffi::OsStrExt,
+    collections::BTreeMap,
+    ffi::OsStr,
+    fs::File,
     path::{Path, PathBuf},
+    time::SystemTime,
 };
 
-#[derive(Debug, Serialize, Deserialize, PartialEq, Eq)]
+#[derive(Debug, Default, Clone, Serialize, Deserialize, PartialEq, Eq)]
+#[serde(deny_unknown_fields)]
 pub struct Settings {
     pub default_depth: u8,
+    /// Picker command and its arguments, quote a path that contains spaces
     pub picker: Option<String>,
+    /// Name of the first window, `{name}` is replaced with the session name
+    pub window_name: Option<String>,
+    /// Glob patterns for files to list alongside directories
+    pub include_files: Option<Vec<String>>,
+    /// Keys sent to a newly created session, `{path}` is replaced with the selected path
+    pub on_create: Option<String>,
+    /// Shell command listing directories one per line, replaces the built-in search
+    pub source_command: Option<String>,
+    /// Prefix picker entries with a Nerd Font git or folder icon
+    pub display_icons: Option<bool>,
+    /// Where the session name comes from
+    pub name_from: Option<NameSource>,
+    /// Terminal command to attach in when outside tmux, e.g. `alacritty -e`
+    pub terminal: Option<String>,
+    /// Command the session's first window runs instead of the default shell
+    pub shell: Option<String>,
+    /// Windows to create, the first one is the session's first window
+    pub layout: Option<Vec<LayoutWindow>>,
+    /// Arguments appended to the picker command, e.g. `["--layout", "reverse"]`
+    pub picker_extra_args: Option<Vec<String>>,
+    /// Filesystem types not to search, e.g. `["nfs", "fuse"]`
+    pub skip_filesystems: Option<Vec<String>>,
+    /// Paths always listed first in the picker, in this order
+    pub pinned: Option<Vec<String>>,
+    /// Where `tms new --root` creates directories, defaults to the first search path
+    pub new_root: Option<String>,
+    /// tmux socket name, passed as `-L`
+    pub tmux_socket: Option<String>,
+    /// tmux socket path, passed as `-S`
+    pub tmux_socket_path: Option<String>,
+    /// `off`, `error`, `warn`, `info` (default), `debug` or `trace`, `RUST_LOG` takes priority
+    pub log_level: Option<String>,
+    /// What selecting a session does when already inside tmux
+    pub inside_tmux_action: Option<InsideAction>,
+    /// List entries grouped by search path in config order, sorted by path within each
+    pub group_by_root: Option<bool>,
+    /// Separate picker entries with NUL, the picker must support fzf's `--read0` and `--print0`
+    pub picker_null_delimited: Option<bool>,
+    /// Start the picker on the directory containing the current directory
+    pub preselect_cwd: Option<bool>,
+    /// Descend into symlinked directories, links back into a parent are skipped
+    pub follow_symlinks: Option<bool>,
+    /// Open the selected directory in a `display-popup` shell instead of a session
+    pub popup: Option<bool>,
+    /// Leave the current session's directory out of the list when inside tmux
+    pub exclude_current: Option<bool>,
+    /// Replacements applied to session names in order, defaults to `.` → `_`, empty keeps names as is
+    pub name_replace: Option<Vec<NameReplace>>,
+    /// Start `shell` as a login shell, or `$SHELL` when `shell` is unset
+    pub shell_login: Option<bool>,
+    /// Resolve symlinks in the selected path before naming and starting the session
+    pub name_from_realpath: Option<bool>,
+    /// What selecting a directory opens
+    pub mode: Option<Mode>,
+    /// Where `tms scratch` starts the scratch session, defaults to `~`
+    pub scratch_path: Option<String>,
+    /// Prefix entries with the directory directly below their search path, e.g. `[work]`
+    pub group_labels: Option<bool>,
+    /// Upper limit for `default_depth` and every search path's `depth`
+    pub max_depth_cap: Option<u8>,
+    /// tmux config file, passed as `-f`
+    pub tmux_config: Option<String>,
+    /// Name file entries by their stem, so `notes.md` opens `notes`
+    pub strip_extension_for_name: Option<bool>,
+    /// fzf `--header`, `{count}` is the number of entries and `{profile}` the active profile
+    pub picker_header: Option<String>,
+    /// Switch instead of attaching inside tmux, and warn before attaching to a session
+    /// another client already has open
+    pub no_steal: Option<bool>,
+    /// Pass `--exact` to fzf pickers for exact instead of fuzzy matching
+    pub picker_exact: Option<bool>,
+    /// Name a new session `name-2`, `name-3`, ... when `name` is taken by a session in another directory
+    pub auto_suffix: Option<bool>,
+    /// Directory of files listing extra paths to show, one per line
+    pub bookmarks_dir: Option<String>,
+    /// Start the first window through `direnv exec`, so it has the directory's direnv environment
+    pub use_direnv: Option<bool>,
+    /// Default `show_hidden` for search paths that don't set their own
+    pub show_hidden: Option<ShowHidden>,
+    /// Show paths that aren't valid UTF-8 with replacement characters instead of leaving them out
+    pub lossy_paths: Option<bool>,
+    /// How the default `fzf-tmux` picker opens: `popup`, `split-down` or `split-right`
+    pub picker_tmux_mode: Option<String>,
+    /// Prefix session names with `<profile>/` while a profile is active
+    pub prefix_with_profile: Option<bool>,
+    /// Leave out found paths that resolve to somewhere outside the search paths, e.g. through symlinks
+    pub strict_roots: Option<bool>,
+    /// Leave out directories whose only content is a single subdirectory, so the chain
+    /// `org/repo/src` is listed as just `src`. Directories holding an `include_files`
+    /// match, branching directories and the ones at the depth limit are always listed
+    pub collapse_single_child: Option<bool>,
+    /// `tmux set-option` arguments applied to every new session, e.g. `destroy-unattached off`
+    pub session_options: Option<Vec<String>>,
+    /// Only search directories owned by the current user
+    pub owned_only: Option<bool>,
+    /// Milliseconds to wait before sending `on_create`, for shells that drop keys while starting
+    pub on_create_delay_ms: Option<u64>,
+    /// Append the checked out branch to git repositories in the picker, e.g. `~/code/api (main)`
+    pub show_git_branch: Option<bool>,
+    /// Regexes for directory names to skip while searching, e.g. `^\d{4}-\d{2}-\d{2}$`
+    pub exclude_regex: Option<Vec<String>>,
+    /// Open the picker a second time when it's closed without a selection
+    pub retry_on_cancel: Option<bool>,
+    /// Env vars set in every new session, a search path's `env` adds to and overrides these
+    pub env: Option<BTreeMap<String, String>>,
+}
+
+impl Settings {
+    /// Overrides settings from `TMS_*` env vars looked up with `var`, see the README for the list
+    pub fn apply_env(&mut self, var: impl Fn(&str) -> Option<String>) -> Result<(), Error> {
+        fn parse<T: std::str::FromStr>(
+            name: &str,
+            value: &str,
+            expected: &str,
+        ) -> Result<T, Error> {
+            value.parse().map_err(|_| {
+                Error::EnvError(format!("invalid {name} '{value}', expected {expected}"))
+            })
+        }
+
+        if let Some(v) = var("TMS_DEFAULT_DEPTH") {
+            self.default_depth = parse("TMS_DEFAULT_DEPTH", &v, "a number from 0 to 255")?;
+        }
+
+        if let Some(v) = var("TMS_SHOW_HIDDEN") {
+            self.show_hidden = Some(match v.as_str() {
+                "top" => ShowHidden::Top,
+                _ => parse::<bool>("TMS_SHOW_HIDDEN", &v, "true, false or top")?.into(),
+            });
+        }
+
+        for (name, setting) in [
+            ("TMS_DISPLAY_ICONS", &mut self.display_icons),
+            ("TMS_FOLLOW_SYMLINKS", &mut self.follow_symlinks),
+            ("TMS_GROUP_BY_ROOT", &mut self.group_by_root),
+        ] {
+            if let Some(v) = var(name) {
+                *setting = Some(parse(name, &v, "true or false")?);
+            }
+        }
+
+        for (name, setting) in [
+            ("TMS_WINDOW_NAME", &mut self.window_name),
+            ("TMS_TERMINAL", &mut self.terminal),
+            ("TMS_SHELL", &mut self.shell),
+            ("TMS_TMUX_SOCKET", &mut self.tmux_socket),
+            ("TMS_LOG_LEVEL", &mut self.log_level),
+        ] {
+            if let Some(v) = var(name) {
+                *setting = Some(v);
+            }
+        }
+
+        Ok(())
+    }
+}
+
+/// Project local overrides read from `settings` in `.tms.yml` in the selected directory,
+/// the same file [`Config::try_open`] reads as a layer when run from that directory
+#[derive(Debug, Default, Deserialize, PartialEq, Eq)]
+pub struct ProjectSettings {
+    pub on_create: Option<String>,
+    pub layout: Option<Vec<LayoutWindow>>,
+    pub window_name: Option<String>,
+    pub shell: Option<String>,
+}
+
+/// `.tms.yml` as [`ProjectSettings::load`] reads it, `paths` only matters as a config layer
+#[derive(Deserialize)]
+#[serde(deny_unknown_fields)]
+struct ProjectFile {
+    #[serde(default)]
+    settings: Mapping,
+    #[serde(default)]
+    #[allow(dead_code)]
+    paths: Vec<Value>,
+}
+
+impl ProjectSettings {
+    const FILE_NAME: &str = ".tms.yml";
+    const FIELDS: [&str; 4] = ["on_create", "layout", "window_name", "shell"];
+
+    pub fn load(dir: &Path) -> Result<Option<Self>, Error> {
+        let path = dir.join(Self::FILE_NAME);
+        if !path.is_file() {
+            return Ok(None);
+        }
+
+        let error = |e: serde_yml::Error| Error::file_error(format!("{}: {e}", path.display()));
+        let file: ProjectFile = serde_yml::from_reader(File::open(&path)?).map_err(error)?;
+        for key in file.settings.keys() {
+            if !Self::FIELDS.contains(&key.as_str()) {
+                log::warn!(
+                    "{}: settings.{} only applies when tms runs from this directory",
+                    path.display(),
+                    key.as_str()
+                );
+            }
+        }
+
+        serde_yml::from_value(Value::Mapping(file.settings))
+            .map(Some)
+            .map_err(error)
+    }
+
+    /// Returns `settings` with every field set here replaced
+    pub fn merge_over(self, settings: &Settings) -> Settings {
+        Settings {
+            on_create: self.on_create.or_else(|| settings.on_create.clone()),
+            layout: self.layout.or_else(|| settings.layout.clone()),
+            window_name: self.window_name.or_else(|| settings.window_name.clone()),
+            shell: self.shell.or_else(|| settings.shell.clone()),
+            ..settings.clone()
+        }
+    }
+}
+
+#[derive(Debug, Default, Clone, Copy, Serialize, Deserialize, PartialEq, Eq)]
+#[serde(rename_all = "lowercase")]
+pub enum NameSource {
+    /// The selected directory's name
+    #[default]
+    Dir,
+    /// The name in `package.json`, `Cargo.toml` or `pyproject.toml`, falling back to the directory name
+    Project,
+}
+
+#[derive(Debug, Default, Clone, Copy, Serialize, Deserialize, PartialEq, Eq)]
+#[serde(rename_all = "lowercase")]
+pub enum Mode {
+    /// A session per directory
+    #[default]
+    Session,
+    /// A window in the current session when inside tmux, a session otherwise
+    Window,
+}
+
+/// A window in `layout`, written as its name or as `{ name, command }`
+#[derive(Debug, Clone, Serialize, Deserialize, PartialEq, Eq)]
+#[serde(from = "LayoutWindowValue")]
+pub struct LayoutWindow {
+    /// `{name}` is replaced with the session name
+    pub name: String,
+    /// Typed into the window once it's created
+    pub command: Option<String>,
+}
+
+#[derive(Deserialize)]
+#[serde(untagged)]
+enum LayoutWindowValue {
+    Name(String),
+    Window {
+        name: String,
+        command: Option<String>,
+    },
+}
+impl From<LayoutWindowValue> for LayoutWindow {
+    fn from(value: LayoutWindowValue) -> Self {
+        match value {
+            LayoutWindowValue::Name(name) => Self {
+                name,
+                command: None,
+            },
+            LayoutWindowValue::Window { name, command } => Self { name, command },
+        }
+    }
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize, PartialEq, Eq)]
+pub struct NameReplace {
+    pub from: String,
+    pub to: String,
+}
+
+#[derive(Debug, Default, Clone, Copy, Serialize, Deserialize, PartialEq, Eq)]
+#[serde(rename_all = "kebab-case")]
+pub enum InsideAction {
+    /// Switch the current client to the session
+    #[default]
+    Switch,
+    /// Attach another client, in `terminal` if set
+    Attach,
+    /// Open a window in the current session with a nested client attached to the session
+    NewWindowToSession,
+}
+
+/// Which hidden entries a search path lists, written as `true`, `false` or `"top"`
+#[derive(Debug, Default, Clone, Copy, Serialize, Deserialize, PartialEq, Eq)]
+#[serde(try_from = "ShowHiddenValue", into = "ShowHiddenValue")]
+pub enum ShowHidden {
+    #[default]
+    Off,
+    All,
+    /// Only entries directly below the search path
+    Top,
+}
+impl ShowHidden {
+    /// Whether hidden entries `level` deep are listed, the search path's children are at level 1
+    pub fn at(self, level: u8) -> bool {
+        match self {
+            Self::Off => false,
+            Self::All => true,
+            Self::Top => level == 1,
+        }
+    }
+}
+impl From<bool> for ShowHidden {
+    fn from(show: bool) -> Self {
+        if show { Self::All } else { Self::Off }
+    }
+}
+
+#[derive(Serialize, Deserialize)]
+#[serde(untagged)]
+enum ShowHiddenValue {
+    Bool(bool),
+    Name(String),
+}
+impl TryFrom<ShowHiddenValue> for ShowHidden {
+    type Error = String;
+
+    fn try_from(value: ShowHiddenValue) -> Result<Self, Self::Error> {
+        match value {
+            ShowHiddenValue::Bool(show) => Ok(show.into()),
+            ShowHiddenValue::Name(name) if name == "top" => Ok(Self::Top),
+            ShowHiddenValue::Name(name) => Err(format!(
+                "show_hidden must be true, false or \"top\", not '{name}'"
+            )),
+        }
+    }
+}
+impl From<ShowHidden> for ShowHiddenValue {
+    fn from(show: ShowHidden) -> Self {
+        match show {
+            ShowHidden::Off => Self::Bool(false),
+            ShowHidden::All => Self::Bool(true),
+            ShowHidden::Top => Self::Name("top".into()),
+        }
+    }
 }
 
 #[derive(Debug, Serialize, Deserialize, PartialEq, Eq)]
@@ -21,7 +365,19 @@ pub enum SearchPath {
     Complex {
         path: String,
         depth: Option<u8>,
-        show_hidden: Option<bool>,
+        /// Only list directories at least this deep, shallower ones are still searched
+        min_depth: Option<u8>,
+        show_hidden: Option<ShowHidden>,
+        /// fzf preview command for entries under this path, `{path}` is replaced with the entry
+        preview: Option<String>,
+        /// Entries of paths with a higher weight are listed first, defaults to 0
+        weight: Option<i32>,
+        /// `tmux set-option` arguments for sessions created under this path, after the global ones
+        session_options: Option<Vec<String>>,
+        /// Regexes for directory names to skip under this path, on top of the global ones
+        exclude_regex: Option<Vec<String>>,
+        /// Env vars for sessions created under this path, values are expanded like the path
+        env: Option<BTreeMap<String, String>>,
     },
 }
 impl SearchPath {
@@ -40,54 +396,310 @@ impl SearchPath {
     }
 
     pub fn expand(&self) -> Result<Self, Error> {
-        fn expand(s: &str) -> Result<String, Error> {
-            shellexpand::full(s)
-                .map_err(|e| Error::EnvError(e.to_string()))
-                .map(|s| s.to_string())
-        }
+        self.expand_with_home(std::env::var("HOME").ok())
+    }
+
+    /// Expands `~` to `home` and env vars, errors name the path that failed
+    fn expand_with_home(&self, home: Option<String>) -> Result<Self, Error> {
+        let expand = |s: &str| -> Result<String, Error> {
+            if home.is_none() && (s == "~" || s.starts_with("~/")) {
+                return Err(Error::EnvError(format!(
+                    "can't expand '{s}', 'HOME' is not set"
+                )));
+            }
+
+            let expanded = shellexpand::full_with_context(
+                s,
+                || home.as_deref(),
+                |var| std::env::var(var).map(Some),
+            )
+            .map_err(|e| Error::EnvError(format!("can't expand '{s}', {e}")))
+            .map(|s| s.to_string())?;
+
+            log::debug!("Expanded '{s}' to '{expanded}'");
+            Ok(expanded)
+        };
 
         match self {
             Self::Simple(s) => Ok(Self::Simple(expand(s)?)),
             Self::Complex {
                 path,
                 depth,
+                min_depth,
                 show_hidden,
+                preview,
+                weight,
+                session_options,
+                exclude_regex,
+                env,
             } => Ok(Self::Complex {
                 path: expand(path)?,
                 depth: *depth,
+                min_depth: *min_depth,
                 show_hidden: *show_hidden,
+                preview: preview.clone(),
+                weight: *weight,
+                session_options: session_options.clone(),
+                exclude_regex: exclude_regex.clone(),
+                env: env
+                    .as_ref()
+                    .map(|env| {
+                        env.iter()
+                            .map(|(k, v)| Ok((k.clone(), expand(v)?)))
+                            .collect::<Result<_, Error>>()
+                    })
+                    .transpose()?,
             }),
         }
     }
 
-    pub fn show_hidden(&self) -> bool {
+    pub fn min_depth(&self) -> u8 {
+        match self {
+            Self::Simple(_) => 0,
+            Self::Complex { min_depth, .. } => min_depth.unwrap_or(0),
+        }
+    }
+
+    pub fn show_hidden(&self, default: ShowHidden) -> ShowHidden {
+        match self {
+            Self::Simple(_) => default,
+            Self::Complex { show_hidden, .. } => show_hidden.unwrap_or(default),
+        }
+    }
+
+    /// The same search path rooted at `path`
+    pub fn with_path(&self, path: String) -> Self {
+        match self {
+            Self::Simple(_) => Self::Simple(path),
+            Self::Complex {
+                depth,
+                min_depth,
+                show_hidden,
+                preview,
+                weight,
+                session_options,
+                exclude_regex,
+                env,
+                ..
+            } => Self::Complex {
+                path,
+                depth: *depth,
+                min_depth: *min_depth,
+                show_hidden: *show_hidden,
+                preview: preview.clone(),
+                weight: *weight,
+                session_options: session_options.clone(),
+                exclude_regex: exclude_regex.clone(),
+                env: env.clone(),
+            },
+        }
+    }
+
+    pub fn weight(&self) -> i32 {
         match self {
-            Self::Simple(_) => false,
-            Self::Complex { show_hidden, .. } => show_hidden.unwrap_or(false),
+            Self::Simple(_) => 0,
+            Self::Complex { weight, .. } => weight.unwrap_or(0),
         }
     }
+
+    pub fn preview(&self) -> Option<&str> {
+        match self {
+            Self::Simple(_) => None,
+            Self::Complex { preview, .. } => preview.as_deref(),
+        }
+    }
+
+    pub fn exclude_regex(&self) -> &[String] {
+        match self {
+            Self::Simple(_) => &[],
+            Self::Complex { exclude_regex, .. } => exclude_regex.as_deref().unwrap_or_default(),
+        }
+    }
+
+    pub fn env(&self) -> Option<&BTreeMap<String, String>> {
+        match self {
+            Self::Simple(_) => None,
+            Self::Complex { env, .. } => env.as_ref(),
+        }
+    }
+
+    pub fn session_options(&self) -> &[String] {
+        match self {
+            Self::Simple(_) => &[],
+            Self::Complex {
+                session_options, ..
+            } => session_options.as_deref().unwrap_or_default(),
+        }
+    }
+}
+
+/// Index of the root `path` was found under, the deepest matching root wins
+pub fn source_root(roots: &[SearchPath], path: &Path) -> Option<usize> {
+    roots
+        .iter()
+        .enumerate()
+        .filter(|(_, root)| path.starts_with(root.path()))
+        .max_by_key(|(_, root)| root.path().components().count())
+        .map(|(i, _)| i)
+}
+
+/// Compiles `exclude_regex` patterns, naming the first one that doesn't parse
+pub fn compile_excludes<'a>(
+    patterns: impl IntoIterator<Item = &'a String>,
+) -> Result<Vec<Regex>, Error> {
+    patterns
+        .into_iter()
+        .map(|p| {
+            Regex::new(p)
+                .map_err(|e| Error::file_error(format!("Invalid exclude_regex pattern '{p}': {e}")))
+        })
+        .collect()
 }
 
-fn is_hidden_path<P: AsRef<Path>>(path: P) -> bool {
-    path.as_ref()
-        .file_name()
-        .map(|n| n.as_bytes()[0] == b'.')
-        .unwrap_or(false)
+/// Builders for tests, which only set the fields they check
+#[cfg(test)]
+impl SearchPath {
+    /// A `Complex` path with every option unset
+    pub fn complex(path: impl Into<String>) -> Self {
+        Self::Complex {
+            path: path.into(),
+            depth: None,
+            min_depth: None,
+            show_hidden: None,
+            preview: None,
+            weight: None,
+            session_options: None,
+            exclude_regex: None,
+            env: None,
+        }
+    }
+
+    pub fn with_depth(mut self, value: u8) -> Self {
+        if let Self::Complex { depth, .. } = &mut self {
+            *depth = Some(value);
+        }
+        self
+    }
+
+    pub fn with_min_depth(mut self, value: u8) -> Self {
+        if let Self::Complex { min_depth, .. } = &mut self {
+            *min_depth = Some(value);
+        }
+        self
+    }
+
+    pub fn with_show_hidden(mut self, value: ShowHidden) -> Self {
+        if let Self::Complex { show_hidden, .. } = &mut self {
+            *show_hidden = Some(value);
+        }
+        self
+    }
+
+    pub fn with_preview(mut self, value: &str) -> Self {
+        if let Self::Complex { preview, .. } = &mut self {
+            *preview = Some(value.into());
+        }
+        self
+    }
+
+    pub fn with_exclude_regex(mut self, value: &[&str]) -> Self {
+        if let Self::Complex { exclude_regex, .. } = &mut self {
+            *exclude_regex = Some(value.iter().map(|r| r.to_string()).collect());
+        }
+        self
+    }
 }
 
 #[derive(Debug, PartialEq, Eq, Clone, Copy)]
 pub enum CacheStatus {
     Hit,
     Miss,
+    /// Loaded from a layer that must not be cached
+    Uncacheable,
+    /// The cache was skipped for this run, see [`CacheMode::Skip`]
+    Bypassed,
+}
+
+/// How [`Config::try_open`] uses the cache
+#[derive(Debug, Default, PartialEq, Eq, Clone, Copy)]
+pub enum CacheMode {
+    /// Read the cache when it's newer than the config files
+    #[default]
+    Use,
+    /// Read the config files and leave the cache alone
+    Skip,
+    /// Read the config files and rewrite the cache
+    Refresh,
+}
+
+/// Extensions probed for `~/.config/tms.*`, in order
+const CONFIG_EXTENSIONS: [&str; 4] = ["yml", "yaml", "json", "toml"];
+
+/// Parses a config file in the format its extension names, anything else is read as YAML
+fn read_layer(path: &Path) -> Result<Value, Error> {
+    let contents = std::fs::read_to_string(path)?;
+    let error = |e: String| Error::file_error(format!("{}: {e}", path.display()));
+
+    match path.extension().and_then(OsStr::to_str) {
+        Some("json") => serde_json::from_str(&contents).map_err(|e| error(e.to_string())),
+        Some("toml") => toml::from_str(&contents).map_err(|e| error(e.to_string())),
+        _ => serde_yml::from_str(&contents).map_err(|e| error(e.to_string())),
+    }
+}
+
+/// Joins the relative `paths` of `layer` onto `dir`, the directory of its file.
+/// Paths starting with `~` or an env var are left for [`SearchPath::expand`]
+fn resolve_layer_paths(layer: &mut Mapping, dir: &Path) {
+    let resolve = |value: &mut Value| {
+        if let Value::String(path) = value
+            && !(path.starts_with('~') || path.starts_with('$') || Path::new(path).is_absolute())
+        {
+            *path = dir.join(&*path).to_string_lossy().into_owned();
+        }
+    };
+
+    let Some(Value::Sequence(paths)) = layer.get_mut("paths") else {
+        return;
+    };
+    for path in paths {
+        match path {
+            Value::Mapping(complex) => {
+                if let Some(path) = complex.get_mut("path") {
+                    resolve(path);
+                }
+            }
+            path => resolve(path),
+        }
+    }
+}
+
+fn merge_layer(config: &mut Mapping, layer: Mapping) {
+    for (key, value) in layer {
+        match (config.get_mut(&key), value) {
+            (Some(Value::Sequence(paths)), Value::Sequence(more)) if key == "paths" => {
+                paths.extend(more);
+            }
+            (Some(Value::Mapping(settings)), Value::Mapping(overrides)) if key == "settings" => {
+                for (field, value) in overrides {
+                    settings.insert(field, value);
+                }
+            }
+            (_, value) => {
+                config.insert(key, value);
+            }
+        }
+    }
 }
 
 #[derive(Debug, Serialize, Deserialize, PartialEq, Eq)]
+#[serde(deny_unknown_fields)]
 pub struct Config {
     pub settings: Settings,
     pub paths: Vec<SearchPath>,
 }
 impl Config {
     const CONFIG_FILE_NAME: &str = "tms";
+    const SYSTEM_CONFIG_FILE: &str = "/etc/tms.yml";
 
     /// Caches to binary file in `~/.cache/tms.bin`
     pub fn cache_binary(&self) -> Result<(), Error> {
@@ -103,9 +715,9 @@ impl Config {
             .create(true)
             .write(true)
             .truncate(true)
-            .open(&cache_new)
-            .map_err(|e| Error::FileError(e.to_string()))?;
+            .open(&cache_new)?;
 
+        write_cache_header(&mut cache_file)?;
         Self::write_binary(self, &mut cache_file)?;
 
         std::fs::rename(cache_new, cache_old)
@@ -116,147 +728,473 @@ impl Config {
 
     fn load_cached_file(path: &Path) -> Result<Self, Error> {
         File::open(path)
-            .map_err(|e| Error::FileError(e.to_string()))
-            .and_then(|mut x| Self::read_binary(&mut x))
+            .map_err(Error::from)
+            .and_then(|mut x| read_cache_header(&mut x).and_then(|_| Self::read_binary(&mut x)))
     }
 
-    pub fn try_open() -> Result<(CacheStatus, Self), Error> {
+    /// The cached config at `cache_file_path`, if `mode` allows it and it's newer than `layers`
+    fn load_cache(cache_file_path: &Path, layers: &[PathBuf], mode: CacheMode) -> Option<Self> {
+        if mode != CacheMode::Use || !layers.iter().all(|p| p.exists()) || !cache_file_path.exists()
+        {
+            return None;
+        }
+
+        let cache_mtime = std::fs::metadata(cache_file_path).and_then(|x| x.modified());
+        let config_mtime = layers
+            .iter()
+            .map(|p| std::fs::metadata(p).and_then(|x| x.modified()))
+            .try_fold(SystemTime::UNIX_EPOCH, |newest, mtime| {
+                mtime.map(|m| newest.max(m))
+            });
+
+        match (config_mtime, cache_mtime) {
+            (Ok(config), Ok(cache)) if cache > config => {}
+            (Err(_), Ok(_)) => {}
+            _ => return None,
+        }
+
+        let config = Self::load_cached_file(cache_file_path).ok()?;
+        log::debug!("Loaded config from cache '{}'", cache_file_path.display());
+        Some(config)
+    }
+
+    /// Written by `tms config edit` when there's no config file yet
+    pub const TEMPLATE: &str = "settings:\n  default_depth: 3\npaths:\n  - ~/Code\n";
+
+    /// `env_config` (`TMS_CONFIG`), or the first existing `~/.config/tms.*`
+    fn user_config_file(home: &Path, env_config: Option<String>) -> Option<PathBuf> {
+        if let Some(path) = env_config {
+            return Some(PathBuf::from(path));
+        }
+
+        let config_path = home.join(".config");
+        CONFIG_EXTENSIONS.iter().find_map(|ext| {
+            let path = config_path.join(format!("{}.{ext}", Self::CONFIG_FILE_NAME));
+            if path.exists() { Some(path) } else { None }
+        })
+    }
+
+    /// The config file to edit, `~/.config/tms.yml` when there's none yet
+    pub fn edit_path(home: &Path, env_config: Option<String>) -> PathBuf {
+        Self::user_config_file(home, env_config).unwrap_or_else(|| {
+            home.join(".config")
+                .join(format!("{}.yml", Self::CONFIG_FILE_NAME))
+        })
+    }
+
+    /// [`Config::user_config_file`], erroring when `TMS_CONFIG` names a missing file instead of
+    /// falling back to `~/.config`
+    fn user_config_layer(
+        home: &Path,
+        env_config: Option<String>,
+    ) -> Result<Option<PathBuf>, Error> {
+        if let Some(path) = &env_config
+            && !Path::new(path).is_file()
+        {
+            return Err(Error::FileError(format!(
+                "TMS_CONFIG is set to '{path}', which doesn't exist"
+            )));
+        }
+
+        Ok(Self::user_config_file(home, env_config))
+    }
+
+    /// The existing config files [`Config::try_open`] merges, in order
+    pub fn layers(home: &Path, env_config: Option<String>) -> Result<Vec<PathBuf>, Error> {
+        let config_file_path = Self::user_config_layer(home, env_config)?;
+        let project_file_path = PathBuf::from(ProjectSettings::FILE_NAME);
+
+        Ok([
+            Some(PathBuf::from(Self::SYSTEM_CONFIG_FILE)).filter(|p| p.is_file()),
+            config_file_path,
+            Some(project_file_path).filter(|p| p.is_file()),
+        ]
+        .into_iter()
+        .flatten()
+        .collect())
+    }
+
+    pub fn try_open(mode: CacheMode) -> Result<(CacheStatus, Self), Error> {
         let home = std::env::var("HOME").expect("'HOME' env var not found");
         let home = PathBuf::from(home);
         let cache_file_path = home.join(".cache").join("tms.bin");
-        let config_path = home.join(".config");
 
-        let config_file_path = if let Ok(config_path) = std::env::var("TMS_CONFIG") {
-            Some(PathBuf::from(config_path))
+        let layers = Self::layers(&home, std::env::var("TMS_CONFIG").ok())?;
+        let has_project_layer = layers
+            .last()
+            .is_some_and(|p| p == Path::new(ProjectSettings::FILE_NAME));
+
+        if layers.is_empty() {
+            return Err(Error::FileError(format!(
+                "Missing config file at '~/.config/{}.yml'",
+                Self::CONFIG_FILE_NAME
+            )));
+        }
+
+        // The project layer depends on the working directory, so it can't be cached
+        if !has_project_layer
+            && let Some(config) = Self::load_cache(&cache_file_path, &layers, mode)
+        {
+            return Ok((CacheStatus::Hit, config));
+        }
+
+        let status = if has_project_layer {
+            CacheStatus::Uncacheable
+        } else if mode == CacheMode::Skip {
+            CacheStatus::Bypassed
         } else {
-            let possible_file_names = [
-                format!("{}.yml", Self::CONFIG_FILE_NAME),
-                format!("{}.yaml", Self::CONFIG_FILE_NAME),
-            ];
-
-            possible_file_names.into_iter().find_map(|name| {
-                let path = config_path.join(name);
-                if path.exists() { Some(path) } else { None }
-            })
+            CacheStatus::Miss
         };
 
-        if let Some(config_file_path) = &config_file_path
-            && config_file_path.exists()
-            && cache_file_path.exists()
-        {
-            let cache_mtime = std::fs::metadata(&cache_file_path).and_then(|x| x.modified());
-            let config_mtime = std::fs::metadata(config_file_path).and_then(|x| x.modified());
+        log::debug!("Loading config from {layers:?}");
+        Self::load_layered(&layers).map(|x| (status, x))
+    }
+
+    /// Reads each config file in order and merges them, later files override
+    /// `settings` field by field and add their `paths` to the earlier ones.
+    /// Relative paths are resolved against the directory of the file they're in.
+    ///
+    /// [`Config::try_open`] uses the layers `/etc/tms.yml`, `~/.config/tms.yml`
+    /// (or `TMS_CONFIG`) and `./.tms.yml`
+    pub fn load_layered(paths: &[PathBuf]) -> Result<Self, Error> {
+        let mut config = Mapping::new();
 
-            match (config_mtime, cache_mtime) {
-                (Ok(config), Ok(cache)) if cache > config => {
-                    if let Ok(config) = Self::load_cached_file(&cache_file_path) {
-                        return Ok((CacheStatus::Hit, config));
+        for path in paths {
+            let layer = read_layer(path)?;
+
+            match layer {
+                Value::Mapping(mut layer) => {
+                    if let Some(dir) = std::path::absolute(path)
+                        .ok()
+                        .as_deref()
+                        .and_then(Path::parent)
+                    {
+                        resolve_layer_paths(&mut layer, dir);
                     }
+                    merge_layer(&mut config, layer);
                 }
-                (Err(_), Ok(_)) => {
-                    if let Ok(config) = Self::load_cached_file(&cache_file_path) {
-                        return Ok((CacheStatus::Hit, config));
-                    }
+                Value::Null => {}
+                _ => {
+                    return Err(Error::file_error(format!(
+                        "{}: expected a mapping",
+                        path.display()
+                    )));
                 }
-
-                _ => {}
             }
         }
 
-        fn read_file(file: File) -> Result<Config, Error> {
-            serde_yml::from_reader(file).map_err(|e| Error::file_error(e.to_string()))
-        }
+        let config: Self = serde_yml::from_value(Value::Mapping(config))?;
+        // Fail here rather than once the search reaches the path
+        compile_excludes(
+            (config.settings.exclude_regex.iter().flatten())
+                .chain(config.paths.iter().flat_map(SearchPath::exclude_regex)),
+        )?;
+
+        Ok(config)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::binary::{WriteBinary, write_cache_header};
+    use crate::config::{
+        CacheMode, Config, LayoutWindow, ProjectSettings, SearchPath, Settings, ShowHidden,
+    };
+    use crate::error::Error;
+    use std::path::{Path, PathBuf};
 
-        match config_file_path {
-            Some(path) => {
-                let file = File::open(path).map_err(|e| Error::FileError(e.to_string()));
+    #[test]
+    fn apply_env_test() {
+        let env = std::collections::HashMap::from([
+            ("TMS_DEFAULT_DEPTH", "3"),
+            ("TMS_DISPLAY_ICONS", "true"),
+            ("TMS_FOLLOW_SYMLINKS", "false"),
+            ("TMS_GROUP_BY_ROOT", "true"),
+            ("TMS_WINDOW_NAME", "editor"),
+            ("TMS_TERMINAL", "kitty"),
+            ("TMS_SHELL", "fish"),
+            ("TMS_TMUX_SOCKET", "work"),
+            ("TMS_LOG_LEVEL", "debug"),
+            ("TMS_SHOW_HIDDEN", "top"),
+        ]);
+        let mut settings = Settings {
+            default_depth: 8,
+            shell: Some("zsh".into()),
+            ..Default::default()
+        };
+        settings
+            .apply_env(|name| env.get(name).map(|v| v.to_string()))
+            .unwrap();
 
-                file.and_then(read_file).map(|x| (CacheStatus::Miss, x))
+        assert_eq!(
+            settings,
+            Settings {
+                default_depth: 3,
+                display_icons: Some(true),
+                follow_symlinks: Some(false),
+                group_by_root: Some(true),
+                window_name: Some("editor".into()),
+                terminal: Some("kitty".into()),
+                shell: Some("fish".into()),
+                tmux_socket: Some("work".into()),
+                log_level: Some("debug".into()),
+                show_hidden: Some(ShowHidden::Top),
+                ..Default::default()
             }
-            None => Err(Error::FileError(format!(
-                "Missing config file at '~/.config/{}.yml'",
-                Self::CONFIG_FILE_NAME
-            ))),
-        }
+        );
+
+        let mut settings = Settings::default();
+        settings
+            .apply_env(|name| (name == "TMS_SHOW_HIDDEN").then(|| "true".to_string()))
+            .unwrap();
+        assert_eq!(settings.show_hidden, Some(ShowHidden::All));
+
+        let err = Settings::default()
+            .apply_env(|name| (name == "TMS_DEFAULT_DEPTH").then(|| "deep".to_string()))
+            .unwrap_err();
+        assert!(
+            err.to_string().contains("TMS_DEFAULT_DEPTH 'deep'"),
+            "{err}"
+        );
     }
 
-    pub fn find_dir_recursive(
-        show_hidden: bool,
-        path: &Path,
-        depth: u8,
-        max_depth: u8,
-    ) -> Vec<PathBuf> {
-        if max_depth == 0 {
-            return vec![];
-        }
+    #[test]
+    fn project_settings_test() {
+        let dir = tempfile::tempdir().unwrap();
+        std::fs::write(
+            dir.path().join(".tms.yml"),
+            "settings:\n  on_create: cargo watch\n  layout: [code, { name: server, command: cargo run }]\npaths: [./sub]\n",
+        )
+        .unwrap();
 
-        fn is_dir(de: &DirEntry) -> bool {
-            de.file_type().map(|ft| ft.is_dir()).unwrap_or(false)
-        }
+        let settings = Settings {
+            default_depth: 3,
+            window_name: Some("{name}".into()),
+            on_create: Some("nvim".into()),
+            shell: Some("zsh".into()),
+            ..Default::default()
+        };
+        let project = ProjectSettings::load(dir.path()).unwrap().unwrap();
 
-        let dir_iter = path
-            .read_dir()
-            .unwrap()
-            .map_while(Result::ok)
-            .par_bridge()
-            .filter(is_dir)
-            .filter(|x| {
-                if show_hidden {
-                    true
-                } else {
-                    !is_hidden_path(x.path())
-                }
-            })
-            .flat_map(|e| {
-                let path = e.path();
-                if depth < max_depth {
-                    let iter = std::iter::once(path.clone()).chain(Self::find_dir_recursive(
-                        show_hidden,
-                        &path,
-                        depth + 1,
-                        max_depth,
-                    ));
-
-                    Vec::from_iter(iter)
-                } else {
-                    vec![path]
-                }
-            });
+        assert_eq!(
+            project.merge_over(&settings),
+            Settings {
+                on_create: Some("cargo watch".into()),
+                layout: Some(vec![
+                    LayoutWindow {
+                        name: "code".into(),
+                        command: None,
+                    },
+                    LayoutWindow {
+                        name: "server".into(),
+                        command: Some("cargo run".into()),
+                    },
+                ]),
+                ..settings
+            }
+        );
+        assert_eq!(
+            ProjectSettings::load(&dir.path().join("missing")).unwrap(),
+            None
+        );
 
-        dir_iter.collect()
+        // The old top level form would be silently ignored otherwise
+        std::fs::write(dir.path().join(".tms.yml"), "on_create: cargo watch\n").unwrap();
+        assert!(matches!(
+            ProjectSettings::load(dir.path()),
+            Err(Error::FileError(e)) if e.contains("unknown field: on_create")
+        ));
     }
 
-    pub fn find_dirs(&self) -> Result<Vec<PathBuf>, Error> {
-        let paths = self
-            .paths
-            .par_iter()
-            .map(|path| path.expand())
-            .filter_map(|x| match x {
-                Ok(p) if p.path().exists() => Some(p),
-                _ => None,
-            })
-            .map(|p| {
-                let depth = p.depth(self.settings.default_depth);
-                let mut paths = Self::find_dir_recursive(p.show_hidden(), p.path(), 1, depth);
+    #[test]
+    fn load_layered_test() {
+        let dir = tempfile::tempdir().unwrap();
+        let system = dir.path().join("system.yml");
+        let user = dir.path().join("user.yml");
+        std::fs::write(
+            &system,
+            "settings:\n  default_depth: 2\n  picker: fzf\npaths:\n  - /srv\n",
+        )
+        .unwrap();
+        std::fs::write(
+            &user,
+            "settings:\n  default_depth: 5\n  window_name: code\npaths:\n  - ~/Code\n",
+        )
+        .unwrap();
 
-                paths.push(p.path().to_path_buf());
+        assert_eq!(
+            Config::load_layered(&[system, user]).unwrap(),
+            Config {
+                settings: Settings {
+                    default_depth: 5,
+                    picker: Some("fzf".into()),
+                    window_name: Some("code".into()),
+                    ..Default::default()
+                },
+                paths: vec![
+                    SearchPath::Simple("/srv".into()),
+                    SearchPath::Simple("~/Code".into())
+                ],
+            }
+        );
 
-                paths
-            });
+        let project = dir.path().join(".tms.yml");
+        std::fs::write(&project, "on_create: nvim\n").unwrap();
+        assert!(Config::load_layered(&[project]).is_err());
+    }
+
+    #[test]
+    fn json_config_test() {
+        let dir = tempfile::tempdir().unwrap();
+        let yml = dir.path().join("tms.yml");
+        let json = dir.path().join("tms.json");
+        std::fs::write(
+            &yml,
+            r#"
+            settings:
+                default_depth: 8
+            paths:
+                - first
+                - path: third
+                  depth: 2
+                - path: fourth
+                  show_hidden: true
+            "#,
+        )
+        .unwrap();
+        std::fs::write(
+            &json,
+            r#"{
+                "settings": { "default_depth": 8 },
+                "paths": [
+                    "first",
+                    { "path": "third", "depth": 2 },
+                    { "path": "fourth", "show_hidden": true }
+                ]
+            }"#,
+        )
+        .unwrap();
 
-        Ok(paths.flatten().collect())
+        assert_eq!(
+            Config::load_layered(&[json]).unwrap(),
+            Config::load_layered(&[yml]).unwrap()
+        );
     }
-}
 
-#[cfg(test)]
-mod tests {
-    use crate::config::is_hidden_path;
+    #[test]
+    fn edit_path_test() {
+        let home = tempfile::tempdir().unwrap();
+        let config = home.path().join(".config");
+        assert_eq!(Config::edit_path(home.path(), None), config.join("tms.yml"));
+
+        std::fs::create_dir(&config).unwrap();
+        std::fs::write(config.join("tms.toml"), "").unwrap();
+        assert_eq!(
+            Config::edit_path(home.path(), None),
+            config.join("tms.toml")
+        );
+        assert_eq!(
+            Config::edit_path(home.path(), Some("/etc/work.yml".into())),
+            PathBuf::from("/etc/work.yml")
+        );
+
+        let template: Config = serde_yml::from_str(Config::TEMPLATE).unwrap();
+        assert_eq!(template.settings.default_depth, 3);
+    }
+
+    #[test]
+    fn missing_tms_config_test() {
+        let home = tempfile::tempdir().unwrap();
+        let config = home.path().join(".config");
+        std::fs::create_dir(&config).unwrap();
+        std::fs::write(config.join("tms.yml"), "").unwrap();
+
+        let missing = home.path().join("typo.yml").to_str().unwrap().to_string();
+        assert!(matches!(
+            Config::user_config_layer(home.path(), Some(missing)),
+            Err(Error::FileError(_))
+        ));
+        assert_eq!(
+            Config::user_config_layer(home.path(), None).unwrap(),
+            Some(config.join("tms.yml"))
+        );
+
+        let work = home.path().join("work.yml");
+        std::fs::write(&work, "").unwrap();
+        let layers = Config::layers(home.path(), Some(work.to_str().unwrap().into())).unwrap();
+        assert!(layers.contains(&work));
+        assert!(!layers.contains(&config.join("tms.yml")));
+    }
+
+    #[test]
+    fn relative_paths_test() {
+        let dir = tempfile::tempdir().unwrap();
+        let file = dir.path().join(".tms.yml");
+        std::fs::write(
+            &file,
+            "settings:\n  default_depth: 1\npaths:\n  - ./projects\n  - path: work\n    depth: 2\n  - ~/Code\n  - /srv\n",
+        )
+        .unwrap();
+
+        let config = Config::load_layered(&[file]).unwrap();
+        assert_eq!(
+            config
+                .paths
+                .iter()
+                .map(SearchPath::path)
+                .collect::<Vec<_>>(),
+            [
+                &dir.path().join("./projects"),
+                &dir.path().join("work"),
+                Path::new("~/Code"),
+                Path::new("/srv"),
+            ]
+        );
+    }
+
+    #[test]
+    fn skip_cache_test() {
+        let dir = tempfile::tempdir().unwrap();
+        let layer = dir.path().join("tms.yml");
+        std::fs::write(&layer, "paths:\n  - ~/Code\n").unwrap();
+        std::fs::File::options()
+            .write(true)
+            .open(&layer)
+            .and_then(|f| f.set_modified(std::time::SystemTime::UNIX_EPOCH))
+            .unwrap();
+
+        let cached = Config {
+            paths: vec![SearchPath::Simple("/cached".into())],
+            settings: Settings::default(),
+        };
+        let cache = dir.path().join("tms.bin");
+        let mut file = std::fs::File::create(&cache).unwrap();
+        write_cache_header(&mut file).unwrap();
+        cached.write_binary(&mut file).unwrap();
+        drop(file);
+
+        let layers = [layer];
+        assert_eq!(
+            Config::load_cache(&cache, &layers, CacheMode::Use),
+            Some(cached)
+        );
+        assert_eq!(Config::load_cache(&cache, &layers, CacheMode::Skip), None);
+        assert_eq!(
+            Config::load_cache(&cache, &layers, CacheMode::Refresh),
+            None
+        );
+    }
 
     #[test]
-    fn hidden_path_test() {
-        assert!(is_hidden_path(".hidden"));
-        assert!(!is_hidden_path("not_hidden"));
-        assert!(is_hidden_path("a/b/.c"));
-        assert!(!is_hidden_path("a/b/c"));
+    fn expand_missing_home_test() {
+        let path = SearchPath::Simple("~/code".into());
+
+        let err = path.expand_with_home(None).unwrap_err();
+        assert!(err.to_string().contains("'~/code'"), "{err}");
+
+        assert_eq!(
+            path.expand_with_home(Some("/home/me".into())).unwrap(),
+            SearchPath::Simple("/home/me/code".into())
+        );
     }
 }
@@ -0,0 +1,1197 @@
+use crate::config::{Config, SearchPath, Settings, ShowHidden, compile_excludes, source_root};
+use crate::error::Error;
+use glob::Pattern;
+use rayon::prelude::*;
+use regex::Regex;
+use std::{
+    collections::{HashMap, HashSet},
+    ffi::OsStr,
+    fs::DirEntry,
+    os::unix::{ffi::OsStrExt, fs::MetadataExt},
+    path::{Component, Path, PathBuf},
+    process::{Command, Stdio},
+};
+
+fn is_hidden_path<P: AsRef<Path>>(path: P) -> bool {
+    path.as_ref()
+        .file_name()
+        .map(|n| n.as_bytes()[0] == b'.')
+        .unwrap_or(false)
+}
+
+/// Type of the filesystem `path` is mounted on, according to `mounts` in `/proc/mounts` format
+fn filesystem_type<'a>(mounts: &'a str, path: &Path) -> Option<&'a str> {
+    mounts
+        .lines()
+        .filter_map(|line| {
+            let mut fields = line.split_whitespace();
+            let _device = fields.next()?;
+            let mount_point = fields.next()?.replace("\\040", " ");
+            let fs_type = fields.next()?;
+
+            path.starts_with(&mount_point)
+                .then_some((mount_point.len(), fs_type))
+        })
+        // The longest mount point is the one `path` is on, later mounts shadow earlier ones
+        .max_by_key(|(len, _)| *len)
+        .map(|(_, fs_type)| fs_type)
+}
+
+/// Existing paths listed in the files in `dir`, one per line, files are read in name order
+fn read_bookmarks(dir: &Path) -> Vec<PathBuf> {
+    let mut files = std::fs::read_dir(dir)
+        .into_iter()
+        .flatten()
+        .filter_map(Result::ok)
+        .map(|e| e.path())
+        .filter(|p| p.is_file())
+        .collect::<Vec<_>>();
+    files.sort();
+
+    files
+        .iter()
+        .filter_map(|f| std::fs::read_to_string(f).ok())
+        .flat_map(|contents| {
+            contents
+                .lines()
+                .map(str::trim)
+                .filter(|l| !l.is_empty())
+                .map(str::to_string)
+                .collect::<Vec<_>>()
+        })
+        .filter_map(|l| SearchPath::Simple(l).expand().ok())
+        .map(|p| p.path().to_path_buf())
+        .filter(|p| p.exists())
+        .collect()
+}
+
+/// Drops the found directories whose only found child is a single directory
+fn collapse_single_child(paths: Vec<PathBuf>) -> Vec<PathBuf> {
+    let mut children = HashMap::<&Path, (usize, bool)>::new();
+    for path in &paths {
+        if let Some(parent) = path.parent() {
+            let (count, has_file) = children.entry(parent).or_default();
+            *count += 1;
+            *has_file |= !path.is_dir();
+        }
+    }
+
+    let collapsed = children
+        .into_iter()
+        .filter(|(_, (count, has_file))| *count == 1 && !has_file)
+        .map(|(parent, _)| parent.to_path_buf())
+        .collect::<HashSet<_>>();
+
+    paths
+        .into_iter()
+        .filter(|p| !collapsed.contains(p))
+        .collect()
+}
+
+/// Drops the paths that resolve to somewhere outside all of the canonical `roots`
+fn within_roots(paths: Vec<PathBuf>, roots: &[PathBuf]) -> Vec<PathBuf> {
+    paths
+        .into_iter()
+        .filter(|p| {
+            let resolved = p.canonicalize().unwrap_or_else(|_| p.clone());
+            let inside = roots.iter().any(|r| resolved.starts_with(r));
+            if !inside {
+                log::warn!(
+                    "skipping '{}', it resolves to '{}' outside the search paths",
+                    p.display(),
+                    resolved.display()
+                );
+            }
+            inside
+        })
+        .collect()
+}
+
+/// Moves `pinned` paths to the front in the given order, adding pinned paths that exist but weren't found
+fn pin_paths(paths: Vec<PathBuf>, pinned: &[PathBuf]) -> Vec<PathBuf> {
+    if pinned.is_empty() {
+        return paths;
+    }
+
+    let mut front = pinned
+        .iter()
+        .filter(|p| paths.contains(p) || p.exists())
+        .cloned()
+        .collect::<Vec<_>>();
+    front.dedup();
+
+    front.extend(paths.into_iter().filter(|p| !pinned.contains(p)));
+    front
+}
+
+/// Drops paths that resolve to one listed before them, which overlapping roots like `~/src`
+/// and `~/src/work` or symlinks produce. Of the copies, the one under the heaviest root stays
+fn dedup_paths(paths: Vec<PathBuf>, roots: &[SearchPath], follow_symlinks: bool) -> Vec<PathBuf> {
+    let canonical = roots
+        .iter()
+        .map(|r| {
+            r.path()
+                .canonicalize()
+                .unwrap_or_else(|_| r.path().to_path_buf())
+        })
+        .collect::<Vec<_>>();
+    let overlapping = canonical
+        .iter()
+        .enumerate()
+        .any(|(i, a)| (canonical.iter().enumerate()).any(|(j, b)| i != j && a.starts_with(b)));
+    // Without overlaps or followed links every path is found once, skip the canonicalizing
+    if !overlapping && !follow_symlinks {
+        return paths;
+    }
+
+    let weight = |p: &Path| source_root(roots, p).map_or(0, |i| roots[i].weight());
+    let mut kept: Vec<PathBuf> = vec![];
+    let mut seen = HashMap::<PathBuf, usize>::new();
+    for path in paths {
+        let resolved = path.canonicalize().unwrap_or_else(|_| path.clone());
+        match seen.get(&resolved) {
+            Some(&i) if weight(&path) > weight(&kept[i]) => kept[i] = path,
+            Some(_) => {}
+            None => {
+                seen.insert(resolved, kept.len());
+                kept.push(path);
+            }
+        }
+    }
+
+    kept
+}
+
+/// Moves entries of roots with a higher weight to the front, keeping the order within a weight
+fn sort_by_weight(mut paths: Vec<PathBuf>, roots: &[SearchPath]) -> Vec<PathBuf> {
+    if roots.iter().all(|r| r.weight() == 0) {
+        return paths;
+    }
+
+    paths.sort_by_cached_key(|p| {
+        std::cmp::Reverse(source_root(roots, p).map_or(0, |i| roots[i].weight()))
+    });
+    paths
+}
+
+/// Orders `paths` by the root they were found under, then by path
+fn group_by_root(mut paths: Vec<PathBuf>, roots: &[SearchPath]) -> Vec<PathBuf> {
+    paths.sort_by_cached_key(|p| (source_root(roots, p).unwrap_or(usize::MAX), p.clone()));
+    paths
+}
+
+/// Drops trailing separators and `.`, and resolves `..` by removing the previous component.
+/// With `follow_symlinks` the path is canonicalized instead, so `..` goes up from a link's target
+fn normalize_root(path: &Path, follow_symlinks: bool) -> PathBuf {
+    if follow_symlinks && let Ok(path) = path.canonicalize() {
+        return path;
+    }
+
+    let mut normalized = PathBuf::new();
+    for component in path.components() {
+        match component {
+            Component::CurDir => {}
+            Component::ParentDir => {
+                if normalized.file_name().is_some() {
+                    normalized.pop();
+                } else if !normalized.has_root() {
+                    normalized.push("..");
+                }
+            }
+            c => normalized.push(c),
+        }
+    }
+
+    normalized
+}
+
+/// Whether the name of `path` matches one of `exclude`
+fn is_excluded(path: &Path, exclude: &[Regex]) -> bool {
+    !exclude.is_empty()
+        && path
+            .file_name()
+            .is_some_and(|name| exclude.iter().any(|r| r.is_match(&name.to_string_lossy())))
+}
+
+/// Whether a directory owned by `uid` passes the `owner` filter, unknown owners don't
+fn is_owned(uid: Option<u32>, owner: Option<u32>) -> bool {
+    owner.is_none() || uid == owner
+}
+
+/// Owner of the directory `entry` is or links to, the entry metadata saves a stat for
+/// anything but symlinks
+fn owner_of(entry: &DirEntry) -> Option<u32> {
+    if entry.file_type().is_ok_and(|ft| ft.is_symlink()) {
+        entry.path().metadata().ok().map(|m| m.uid())
+    } else {
+        entry.metadata().ok().map(|m| m.uid())
+    }
+}
+
+/// The uid of the current user, taken from the owner of `HOME`
+fn current_uid() -> Option<u32> {
+    let home = std::env::var_os("HOME")?;
+    std::fs::metadata(home).ok().map(|m| m.uid())
+}
+
+/// `TMS_SEQUENTIAL=1` searches on a single thread for debugging
+fn is_sequential() -> bool {
+    std::env::var("TMS_SEQUENTIAL").is_ok_and(|v| v == "1")
+}
+
+/// How [`find_dir_recursive`] searches below a path
+#[derive(Debug, Default, Clone, Copy)]
+pub struct WalkOptions<'a> {
+    pub show_hidden: ShowHidden,
+    /// Descend into symlinked directories, skipping the ones that link back to a parent
+    pub follow_symlinks: bool,
+    /// Read the directories on the current thread, which makes traces easier to follow
+    pub sequential: bool,
+    /// Files with a name matching one of these are collected too, but not descended into
+    pub include_files: &'a [Pattern],
+    /// Directories with a name matching one of these are skipped and not descended into
+    pub exclude: &'a [Regex],
+    /// Directories owned by anyone but this uid are skipped and not descended into
+    pub owner: Option<u32>,
+}
+
+/// Collects directories below `path` from level `depth` down to `max_depth`,
+/// one generation at a time so deep trees don't grow the stack
+pub fn find_dir_recursive(
+    options: &WalkOptions,
+    path: &Path,
+    depth: u8,
+    max_depth: u8,
+) -> Vec<PathBuf> {
+    let (found, cycles) = walk_dirs(options, path, depth, max_depth);
+
+    for link in cycles {
+        log::warn!(
+            "skipping '{}', it links back to one of its parent directories",
+            link.display()
+        );
+    }
+
+    found
+}
+
+/// [`find_dir_recursive`], also returning the symlinks skipped because they form a cycle
+fn walk_dirs(
+    options: &WalkOptions,
+    path: &Path,
+    depth: u8,
+    max_depth: u8,
+) -> (Vec<PathBuf>, Vec<PathBuf>) {
+    fn is_dir(de: &DirEntry, follow_symlinks: bool) -> bool {
+        match de.file_type() {
+            Ok(ft) if ft.is_symlink() => follow_symlinks && de.path().is_dir(),
+            Ok(ft) => ft.is_dir(),
+            Err(_) => false,
+        }
+    }
+
+    /// Whether following `link` leads back to a directory it's already inside of
+    fn is_cycle(link: &Path) -> bool {
+        let Ok(target) = link.canonicalize() else {
+            return false;
+        };
+
+        link.ancestors()
+            .skip(1)
+            .any(|p| p.canonicalize().is_ok_and(|p| p == target))
+    }
+
+    fn is_included_file(de: &DirEntry, include_files: &[Pattern]) -> bool {
+        !include_files.is_empty()
+            && de.file_type().map(|ft| ft.is_file()).unwrap_or(false)
+            && de
+                .file_name()
+                .to_str()
+                .is_some_and(|name| include_files.iter().any(|p| p.matches(name)))
+    }
+
+    /// Returns the child directories, the matching files and the cyclic symlinks of `path`,
+    /// `show_hidden` decides for this level
+    fn read_children(
+        show_hidden: bool,
+        options: &WalkOptions,
+        path: &Path,
+    ) -> (Vec<PathBuf>, Vec<PathBuf>, Vec<PathBuf>) {
+        let mut dirs = vec![];
+        let mut files = vec![];
+        let mut cycles = vec![];
+
+        for entry in path.read_dir().into_iter().flatten().map_while(Result::ok) {
+            let path = entry.path();
+            if !show_hidden && is_hidden_path(&path) {
+                continue;
+            }
+
+            if is_dir(&entry, options.follow_symlinks) {
+                if is_excluded(&path, options.exclude) {
+                    log::debug!("skipping '{}', it matches exclude_regex", path.display());
+                } else if options.owner.is_some() && !is_owned(owner_of(&entry), options.owner) {
+                    log::debug!("skipping '{}', it's owned by another user", path.display());
+                } else if entry.file_type().is_ok_and(|ft| ft.is_symlink()) && is_cycle(&path) {
+                    cycles.push(path);
+                } else {
+                    dirs.push(path);
+                }
+            } else if is_included_file(&entry, options.include_files) {
+                files.push(path);
+            }
+        }
+
+        (dirs, files, cycles)
+    }
+
+    let mut found = vec![];
+    let mut all_cycles = vec![];
+    let mut generation = vec![path.to_path_buf()];
+
+    for level in depth..=max_depth {
+        if generation.is_empty() {
+            break;
+        }
+
+        let show_hidden = options.show_hidden.at(level);
+        let read = |p: &PathBuf| read_children(show_hidden, options, p);
+        let children: Vec<_> = if options.sequential {
+            generation.iter().map(read).collect()
+        } else {
+            generation.par_iter().map(read).collect()
+        };
+
+        generation = vec![];
+        for (dirs, files, cycles) in children {
+            found.extend_from_slice(&dirs);
+            found.extend(files);
+            all_cycles.extend(cycles);
+            generation.extend(dirs);
+        }
+    }
+
+    (found, all_cycles)
+}
+
+fn include_file_patterns(settings: &Settings) -> Result<Vec<Pattern>, Error> {
+    settings
+        .include_files
+        .iter()
+        .flatten()
+        .map(|p| {
+            Pattern::new(p)
+                .map_err(|e| Error::file_error(format!("Invalid include_files pattern '{p}': {e}")))
+        })
+        .collect()
+}
+
+fn run_source_command(command: &str) -> Result<Vec<PathBuf>, Error> {
+    let output = Command::new("sh")
+        .args(["-c", command])
+        .stderr(Stdio::inherit())
+        .output()
+        .map_err(|e| Error::SourceCommand(format!("'{command}': {e}")))?;
+
+    if !output.status.success() {
+        return Err(Error::SourceCommand(format!(
+            "'{command}' exited with {}",
+            output.status
+        )));
+    }
+
+    let paths = output
+        .stdout
+        .split(|b| *b == b'\n')
+        .filter(|line| !line.is_empty())
+        .map(|line| PathBuf::from(OsStr::from_bytes(line)))
+        .collect();
+
+    Ok(paths)
+}
+
+/// `root` with its path run through [`normalize_root`]
+fn normalized(root: SearchPath, follow_symlinks: bool) -> SearchPath {
+    let path = normalize_root(root.path(), follow_symlinks);
+    root.with_path(path.to_string_lossy().into_owned())
+}
+
+/// Search paths with `~` and env vars expanded and normalized, in config order
+pub fn roots(config: &Config) -> Vec<SearchPath> {
+    let follow_symlinks = config.settings.follow_symlinks.unwrap_or(false);
+
+    config
+        .paths
+        .iter()
+        .filter_map(|p| p.expand().ok())
+        .map(|p| normalized(p, follow_symlinks))
+        .collect()
+}
+
+pub fn find_dirs(config: &Config) -> Result<Vec<PathBuf>, Error> {
+    let paths = match &config.settings.source_command {
+        Some(command) => run_source_command(command)?,
+        None => search_paths(config)?,
+    };
+    let roots = roots(config);
+    let paths = dedup_paths(
+        paths,
+        &roots,
+        config.settings.follow_symlinks.unwrap_or(false),
+    );
+    let paths = if config.settings.group_by_root.unwrap_or(false) {
+        group_by_root(paths, &roots)
+    } else {
+        paths
+    };
+    let mut paths = sort_by_weight(paths, &roots);
+
+    if let Some(dir) = &config.settings.bookmarks_dir
+        && let Ok(dir) = SearchPath::Simple(dir.clone()).expand()
+    {
+        for bookmark in read_bookmarks(dir.path()) {
+            if !paths.contains(&bookmark) {
+                paths.push(bookmark);
+            }
+        }
+    }
+
+    let pinned = config
+        .settings
+        .pinned
+        .iter()
+        .flatten()
+        .filter_map(|p| SearchPath::Simple(p.clone()).expand().ok())
+        .map(|p| p.path().to_path_buf())
+        .collect::<Vec<_>>();
+
+    Ok(pin_paths(paths, &pinned))
+}
+
+fn search_paths(config: &Config) -> Result<Vec<PathBuf>, Error> {
+    let include_files = include_file_patterns(&config.settings)?;
+    let follow_symlinks = config.settings.follow_symlinks.unwrap_or(false);
+
+    let skip_filesystems = config
+        .settings
+        .skip_filesystems
+        .as_deref()
+        .unwrap_or_default();
+    let mounts = if skip_filesystems.is_empty() {
+        String::new()
+    } else {
+        std::fs::read_to_string("/proc/mounts").unwrap_or_default()
+    };
+
+    let (expanded, failed): (Vec<_>, Vec<_>) = config
+        .paths
+        .iter()
+        .map(SearchPath::expand)
+        .partition(Result::is_ok);
+
+    // One bad path shouldn't hide the rest, only fail if nothing is left
+    let mut errors = failed.into_iter().filter_map(Result::err);
+    if expanded.is_empty()
+        && let Some(e) = errors.next()
+    {
+        return Err(e);
+    }
+    for e in errors {
+        log::warn!("{e}");
+    }
+
+    let roots = expanded
+        .into_iter()
+        .filter_map(Result::ok)
+        .map(|p| normalized(p, follow_symlinks))
+        .filter(|p| p.path().exists())
+        .filter(|p| match filesystem_type(&mounts, p.path()) {
+            Some(fs_type)
+                if skip_filesystems
+                    .iter()
+                    .any(|s| fs_type.starts_with(s.as_str())) =>
+            {
+                log::warn!("skipping '{}' on {fs_type} filesystem", p.path().display());
+                false
+            }
+            _ => true,
+        })
+        .collect::<Vec<_>>();
+
+    let owner = if config.settings.owned_only.unwrap_or(false) {
+        let uid = current_uid();
+        if uid.is_none() {
+            log::warn!("can't tell the current user from HOME, owned_only is ignored");
+        }
+        uid
+    } else {
+        None
+    };
+
+    let excludes = roots
+        .iter()
+        .map(|p| {
+            compile_excludes(
+                (config.settings.exclude_regex.iter().flatten()).chain(p.exclude_regex()),
+            )
+        })
+        .collect::<Result<Vec<_>, _>>()?;
+
+    let options = WalkOptions {
+        follow_symlinks,
+        sequential: is_sequential(),
+        include_files: &include_files,
+        owner,
+        ..Default::default()
+    };
+    let walk = |(p, exclude): (&SearchPath, &Vec<Regex>)| {
+        let mut depth = p.depth(config.settings.default_depth);
+        if let Some(cap) = config.settings.max_depth_cap
+            && depth > cap
+        {
+            log::warn!(
+                "limiting depth {depth} of '{}' to max_depth_cap {cap}",
+                p.path().display()
+            );
+            depth = cap;
+        }
+
+        let options = WalkOptions {
+            show_hidden: p.show_hidden(config.settings.show_hidden.unwrap_or_default()),
+            exclude,
+            ..options
+        };
+        let mut paths = find_dir_recursive(&options, p.path(), 1, depth);
+        if config.settings.collapse_single_child.unwrap_or(false) {
+            paths = collapse_single_child(paths);
+        }
+
+        paths.push(p.path().to_path_buf());
+
+        let min_depth = p.min_depth() as usize;
+        if min_depth > 0 {
+            let root_depth = p.path().components().count();
+            paths.retain(|d| d.components().count() - root_depth >= min_depth);
+        }
+
+        log::debug!(
+            "Found {} entries under '{}'",
+            paths.len(),
+            p.path().display()
+        );
+        paths
+    };
+
+    let paths: Vec<_> = if options.sequential {
+        roots.iter().zip(&excludes).map(walk).collect()
+    } else {
+        roots.par_iter().zip(&excludes).map(walk).collect()
+    };
+    let paths = paths.into_iter().flatten().collect();
+
+    if config.settings.strict_roots.unwrap_or(false) {
+        let roots = roots
+            .iter()
+            .map(|r| {
+                r.path()
+                    .canonicalize()
+                    .unwrap_or_else(|_| r.path().to_path_buf())
+            })
+            .collect::<Vec<_>>();
+        return Ok(within_roots(paths, &roots));
+    }
+
+    Ok(paths)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::config::Settings;
+
+    #[test]
+    fn hidden_path_test() {
+        assert!(is_hidden_path(".hidden"));
+        assert!(!is_hidden_path("not_hidden"));
+        assert!(is_hidden_path("a/b/.c"));
+        assert!(!is_hidden_path("a/b/c"));
+    }
+
+    /// The previous recursive traversal, kept as a reference implementation
+    fn find_dir_recursive_reference(
+        show_hidden: bool,
+        path: &Path,
+        depth: u8,
+        max_depth: u8,
+    ) -> Vec<PathBuf> {
+        if max_depth == 0 {
+            return vec![];
+        }
+
+        let mut found = vec![];
+        for e in path.read_dir().unwrap().map_while(Result::ok) {
+            let path = e.path();
+            if !e.file_type().unwrap().is_dir() || (!show_hidden && is_hidden_path(&path)) {
+                continue;
+            }
+
+            found.push(path.clone());
+            if depth < max_depth {
+                found.extend(find_dir_recursive_reference(
+                    show_hidden,
+                    &path,
+                    depth + 1,
+                    max_depth,
+                ));
+            }
+        }
+
+        found
+    }
+
+    #[test]
+    fn find_dir_recursive_matches_reference_test() {
+        // xorshift, so the generated trees are the same on every run
+        fn next(state: &mut u32) -> u32 {
+            *state ^= *state << 13;
+            *state ^= *state >> 17;
+            *state ^= *state << 5;
+            *state
+        }
+
+        fn build(path: &Path, state: &mut u32, depth: u8) {
+            if depth == 0 {
+                return;
+            }
+
+            for i in 0..next(state) % 4 {
+                let name = match next(state) % 5 {
+                    0 => format!(".hidden_{i}"),
+                    1 => {
+                        std::fs::write(path.join(format!("file_{i}")), "").unwrap();
+                        continue;
+                    }
+                    _ => format!("dir_{i}"),
+                };
+
+                let child = path.join(name);
+                std::fs::create_dir(&child).unwrap();
+                build(&child, state, depth - 1);
+            }
+        }
+
+        for seed in 1..=20 {
+            let dir = tempfile::tempdir().unwrap();
+            let mut state = seed;
+            build(dir.path(), &mut state, 6);
+
+            for (show_hidden, max_depth) in [(false, 0), (false, 3), (true, 3), (true, 8)] {
+                let mut expected =
+                    find_dir_recursive_reference(show_hidden, dir.path(), 1, max_depth);
+                let mut actual = find_dir_recursive(
+                    &WalkOptions {
+                        show_hidden: show_hidden.into(),
+                        ..Default::default()
+                    },
+                    dir.path(),
+                    1,
+                    max_depth,
+                );
+                let sequential = find_dir_recursive(
+                    &WalkOptions {
+                        show_hidden: show_hidden.into(),
+                        sequential: true,
+                        ..Default::default()
+                    },
+                    dir.path(),
+                    1,
+                    max_depth,
+                );
+                assert_eq!(
+                    sequential, actual,
+                    "seed {seed}, depth {max_depth}, sequential"
+                );
+
+                expected.sort();
+                actual.sort();
+
+                assert_eq!(actual, expected, "seed {seed}, depth {max_depth}");
+            }
+        }
+    }
+
+    #[test]
+    fn include_files_test() {
+        let dir = tempfile::tempdir().unwrap();
+        std::fs::create_dir(dir.path().join("notes")).unwrap();
+        std::fs::write(dir.path().join("notes/todo.md"), "").unwrap();
+        std::fs::write(dir.path().join("notes/todo.txt"), "").unwrap();
+
+        let patterns = [glob::Pattern::new("*.md").unwrap()];
+        let mut found = find_dir_recursive(
+            &WalkOptions {
+                include_files: &patterns,
+                ..Default::default()
+            },
+            dir.path(),
+            1,
+            2,
+        );
+        found.sort();
+
+        assert_eq!(
+            found,
+            [dir.path().join("notes"), dir.path().join("notes/todo.md")]
+        );
+    }
+
+    #[test]
+    fn normalize_root_test() {
+        let dir = tempfile::tempdir().unwrap();
+        std::fs::create_dir_all(dir.path().join("code/api")).unwrap();
+        std::fs::create_dir_all(dir.path().join("notes/todo")).unwrap();
+
+        let root = |p: &str| SearchPath::Simple(format!("{}/{p}", dir.path().display()));
+        let config = Config {
+            settings: Settings {
+                default_depth: 1,
+                ..Default::default()
+            },
+            paths: vec![root("code/"), root("code/../notes/.")],
+        };
+
+        assert_eq!(
+            find_dirs(&config).unwrap(),
+            ["code/api", "code", "notes/todo", "notes"].map(|p| dir.path().join(p))
+        );
+
+        assert_eq!(
+            normalize_root(Path::new("../a/./b/../c//"), false),
+            Path::new("../a/c")
+        );
+        assert_eq!(normalize_root(Path::new("/.."), false), Path::new("/"));
+    }
+
+    #[test]
+    fn max_depth_cap_test() {
+        let dir = tempfile::tempdir().unwrap();
+        std::fs::create_dir_all(dir.path().join("a/b/c/d")).unwrap();
+
+        let config = Config {
+            settings: Settings {
+                max_depth_cap: Some(2),
+                ..Default::default()
+            },
+            paths: vec![SearchPath::complex(dir.path().to_str().unwrap()).with_depth(255)],
+        };
+        let mut found = find_dirs(&config).unwrap();
+        found.sort();
+
+        assert_eq!(found, ["", "a", "a/b"].map(|p| dir.path().join(p)));
+    }
+
+    #[test]
+    fn min_depth_test() {
+        let dir = tempfile::tempdir().unwrap();
+        std::fs::create_dir_all(dir.path().join("acme/site")).unwrap();
+        std::fs::create_dir_all(dir.path().join("acme/app/src")).unwrap();
+        std::fs::create_dir_all(dir.path().join("globex/api")).unwrap();
+
+        let config = Config {
+            settings: Settings::default(),
+            paths: vec![
+                SearchPath::complex(dir.path().to_str().unwrap())
+                    .with_depth(2)
+                    .with_min_depth(2),
+            ],
+        };
+        let mut found = find_dirs(&config).unwrap();
+        found.sort();
+
+        assert_eq!(
+            found,
+            ["acme/app", "acme/site", "globex/api"].map(|p| dir.path().join(p))
+        );
+    }
+
+    #[test]
+    fn symlink_cycle_test() {
+        let dir = tempfile::tempdir().unwrap();
+        std::fs::create_dir_all(dir.path().join("code/api")).unwrap();
+        std::fs::create_dir(dir.path().join("shared")).unwrap();
+        std::os::unix::fs::symlink(dir.path().join("code"), dir.path().join("code/api/loop"))
+            .unwrap();
+        std::os::unix::fs::symlink(dir.path().join("shared"), dir.path().join("code/shared"))
+            .unwrap();
+
+        let (mut found, cycles) = walk_dirs(
+            &WalkOptions {
+                follow_symlinks: true,
+                ..Default::default()
+            },
+            dir.path(),
+            1,
+            20,
+        );
+        found.sort();
+
+        assert_eq!(
+            found,
+            ["code", "code/api", "code/shared", "shared"].map(|p| dir.path().join(p))
+        );
+        assert_eq!(cycles, [dir.path().join("code/api/loop")]);
+    }
+
+    #[test]
+    fn source_command_test() {
+        assert_eq!(
+            run_source_command("printf '/code/api\\n/code/web\\n'").unwrap(),
+            [PathBuf::from("/code/api"), PathBuf::from("/code/web")]
+        );
+        assert!(run_source_command("exit 1").is_err());
+    }
+
+    #[test]
+    fn filesystem_type_test() {
+        let mounts = "\
+/dev/nvme0n1p2 / ext4 rw,relatime 0 0
+proc /proc proc rw,nosuid,nodev,noexec,relatime 0 0
+server:/export /home/user/remote nfs4 rw,relatime 0 0
+sshfs#host: /home/user/my\\040mount fuse.sshfs rw 0 0
+";
+
+        assert_eq!(
+            filesystem_type(mounts, Path::new("/home/user/Code")),
+            Some("ext4")
+        );
+        assert_eq!(
+            filesystem_type(mounts, Path::new("/home/user/remote/project")),
+            Some("nfs4")
+        );
+        assert_eq!(
+            filesystem_type(mounts, Path::new("/home/user/my mount")),
+            Some("fuse.sshfs")
+        );
+        assert_eq!(filesystem_type("", Path::new("/home/user")), None);
+    }
+
+    #[test]
+    fn pin_paths_test() {
+        let dir = tempfile::tempdir().unwrap();
+        let extra = dir.path().join("extra");
+        std::fs::create_dir(&extra).unwrap();
+
+        let paths = ["/code/a", "/code/b", "/code/c"]
+            .map(PathBuf::from)
+            .to_vec();
+        let pinned = [
+            PathBuf::from("/code/c"),
+            extra.clone(),
+            PathBuf::from("/missing"),
+        ];
+
+        assert_eq!(
+            pin_paths(paths, &pinned),
+            [
+                PathBuf::from("/code/c"),
+                extra,
+                PathBuf::from("/code/a"),
+                PathBuf::from("/code/b"),
+            ]
+        );
+    }
+
+    #[test]
+    fn show_hidden_top_test() {
+        let dir = tempfile::tempdir().unwrap();
+        for p in [".config/.cache", ".config/nvim", "code/.git"] {
+            std::fs::create_dir_all(dir.path().join(p)).unwrap();
+        }
+
+        let mut found = find_dir_recursive(
+            &WalkOptions {
+                show_hidden: ShowHidden::Top,
+                sequential: true,
+                ..Default::default()
+            },
+            dir.path(),
+            1,
+            2,
+        );
+        found.sort();
+        assert_eq!(
+            found,
+            [".config", ".config/nvim", "code"].map(|p| dir.path().join(p))
+        );
+
+        let path: SearchPath =
+            serde_yml::from_str("{ path: ~/.config, show_hidden: top }").unwrap();
+        assert_eq!(path.show_hidden(ShowHidden::Off), ShowHidden::Top);
+        assert!(serde_yml::from_str::<SearchPath>("{ path: a, show_hidden: some }").is_err());
+    }
+
+    #[test]
+    fn default_show_hidden_test() {
+        let dir = tempfile::tempdir().unwrap();
+        let root = |name: &str| dir.path().join(name).to_str().unwrap().to_string();
+        for p in ["inherit/.dotfiles", "own/.git", "own/src"] {
+            std::fs::create_dir_all(dir.path().join(p)).unwrap();
+        }
+
+        let yml = format!(
+            "settings:\n  default_depth: 1\n  show_hidden: true\npaths:\n  - {}\n  - path: {}\n    show_hidden: false\n",
+            root("inherit"),
+            root("own")
+        );
+        let config: Config = serde_yml::from_str(&yml).unwrap();
+        assert_eq!(config.settings.show_hidden, Some(ShowHidden::All));
+        assert_eq!(
+            config.paths[0].show_hidden(ShowHidden::All),
+            ShowHidden::All
+        );
+        assert_eq!(
+            config.paths[1].show_hidden(ShowHidden::All),
+            ShowHidden::Off
+        );
+
+        let mut found = find_dirs(&config).unwrap();
+        found.sort();
+        assert_eq!(
+            found,
+            ["inherit", "inherit/.dotfiles", "own", "own/src"].map(|p| dir.path().join(p))
+        );
+    }
+
+    #[test]
+    fn nested_roots_test() {
+        let dir = tempfile::tempdir().unwrap();
+        for p in ["src/web", "src/work/api"] {
+            std::fs::create_dir_all(dir.path().join(p)).unwrap();
+        }
+        let config = Config {
+            settings: Settings {
+                default_depth: 2,
+                ..Default::default()
+            },
+            paths: serde_yml::from_str(&format!(
+                "[{0}/src, {{ path: {0}/src/work, weight: 5 }}]",
+                dir.path().display()
+            ))
+            .unwrap(),
+        };
+
+        assert_eq!(
+            find_dirs(&config).unwrap(),
+            ["src/work", "src/work/api", "src/web", "src"].map(|p| dir.path().join(p))
+        );
+    }
+
+    #[test]
+    fn sort_by_weight_test() {
+        let roots: Vec<SearchPath> =
+            serde_yml::from_str("[{ path: /docs, weight: 1 }, { path: /code, weight: 5 }, /misc]")
+                .unwrap();
+        let paths = ["/docs/b", "/misc/a", "/code/z", "/docs/a", "/code/y"]
+            .map(PathBuf::from)
+            .to_vec();
+
+        assert_eq!(
+            sort_by_weight(paths, &roots),
+            ["/code/z", "/code/y", "/docs/b", "/docs/a", "/misc/a"].map(PathBuf::from)
+        );
+    }
+
+    #[test]
+    fn strict_roots_test() {
+        let dir = tempfile::tempdir().unwrap();
+        let root = dir.path().join("root");
+        let outside = dir.path().join("outside");
+        std::fs::create_dir_all(root.join("api")).unwrap();
+        std::fs::create_dir(&outside).unwrap();
+        std::os::unix::fs::symlink(&outside, root.join("escape")).unwrap();
+
+        let config = |strict_roots| Config {
+            paths: vec![SearchPath::Simple(root.to_str().unwrap().into())],
+            settings: Settings {
+                default_depth: 1,
+                follow_symlinks: Some(true),
+                strict_roots: Some(strict_roots),
+                ..Default::default()
+            },
+        };
+
+        let mut found = find_dirs(&config(false)).unwrap();
+        found.sort();
+        assert_eq!(found, [root.clone(), root.join("api"), root.join("escape")]);
+
+        let mut found = find_dirs(&config(true)).unwrap();
+        found.sort();
+        assert_eq!(found, [root.clone(), root.join("api")]);
+    }
+
+    #[test]
+    fn collapse_single_child_test() {
+        let dir = tempfile::tempdir().unwrap();
+        let chain = dir.path().join("org/repo/src");
+        std::fs::create_dir_all(&chain).unwrap();
+        std::fs::create_dir_all(dir.path().join("notes/work")).unwrap();
+        std::fs::write(dir.path().join("notes/todo.md"), "").unwrap();
+
+        let config = Config {
+            paths: vec![SearchPath::Simple(dir.path().to_str().unwrap().into())],
+            settings: Settings {
+                default_depth: 3,
+                include_files: Some(vec!["*.md".into()]),
+                collapse_single_child: Some(true),
+                ..Default::default()
+            },
+        };
+
+        let mut found = find_dirs(&config).unwrap();
+        found.sort();
+        assert_eq!(
+            found,
+            [
+                dir.path().to_path_buf(),
+                dir.path().join("notes"),
+                dir.path().join("notes/todo.md"),
+                dir.path().join("notes/work"),
+                chain,
+            ]
+        );
+    }
+
+    #[test]
+    fn exclude_regex_test() {
+        let dir = tempfile::tempdir().unwrap();
+        for sub in [
+            "2024-01-31/notes",
+            "api",
+            "archive/2023-12-01",
+            "archive/drafts",
+        ] {
+            std::fs::create_dir_all(dir.path().join(sub)).unwrap();
+        }
+
+        let config = Config {
+            paths: vec![
+                SearchPath::complex(dir.path().to_str().unwrap())
+                    .with_depth(3)
+                    .with_exclude_regex(&[r"^\d{4}-\d{2}-\d{2}$"]),
+            ],
+            settings: Settings::default(),
+        };
+        let mut found = find_dirs(&config).unwrap();
+        found.sort();
+        assert_eq!(
+            found,
+            ["", "api", "archive", "archive/drafts"].map(|p| dir.path().join(p))
+        );
+
+        let file = dir.path().join("tms.yml");
+        std::fs::write(
+            &file,
+            "settings:\n  default_depth: 1\n  exclude_regex: ['(']\npaths: []\n",
+        )
+        .unwrap();
+        assert!(matches!(
+            Config::load_layered(&[file]),
+            Err(Error::FileError(e)) if e.starts_with("Invalid exclude_regex pattern '('")
+        ));
+    }
+
+    #[test]
+    fn owned_only_test() {
+        assert!(is_owned(Some(1000), None));
+        assert!(is_owned(None, None));
+        assert!(is_owned(Some(1000), Some(1000)));
+        assert!(!is_owned(Some(0), Some(1000)));
+        assert!(!is_owned(None, Some(1000)));
+
+        let dir = tempfile::tempdir().unwrap();
+        std::fs::create_dir_all(dir.path().join("mine/api")).unwrap();
+        let uid = current_uid().unwrap();
+        let mut found = find_dir_recursive(
+            &WalkOptions {
+                sequential: true,
+                owner: Some(uid),
+                ..Default::default()
+            },
+            dir.path(),
+            1,
+            2,
+        );
+        found.sort();
+        assert_eq!(
+            found,
+            [dir.path().join("mine"), dir.path().join("mine/api")]
+        );
+
+        assert!(
+            find_dir_recursive(
+                &WalkOptions {
+                    sequential: true,
+                    owner: Some(uid + 1),
+                    ..Default::default()
+                },
+                dir.path(),
+                1,
+                2
+            )
+            .is_empty()
+        );
+    }
+
+    #[test]
+    fn read_bookmarks_test() {
+        let projects = tempfile::tempdir().unwrap();
+        let api = projects.path().join("api");
+        let web = projects.path().join("web");
+        std::fs::create_dir(&api).unwrap();
+        std::fs::create_dir(&web).unwrap();
+
+        let bookmarks = tempfile::tempdir().unwrap();
+        std::fs::write(
+            bookmarks.path().join("a"),
+            format!(
+                "{}\n\n{}\n",
+                api.display(),
+                projects.path().join("gone").display()
+            ),
+        )
+        .unwrap();
+        std::fs::write(bookmarks.path().join("b"), web.to_str().unwrap()).unwrap();
+
+        assert_eq!(read_bookmarks(bookmarks.path()), [api, web]);
+        assert!(read_bookmarks(&projects.path().join("missing")).is_empty());
+    }
+
+    #[test]
+    fn group_by_root_test() {
+        let roots = [
+            SearchPath::Simple("/home/me/Documents".into()),
+            SearchPath::Simple("/home/me/Code".into()),
+        ];
+        let paths = [
+            "/home/me/Code/web",
+            "/home/me/Documents/notes",
+            "/other",
+            "/home/me/Code/api",
+            "/home/me/Documents",
+        ]
+        .map(PathBuf::from)
+        .to_vec();
+
+        assert_eq!(
+            group_by_root(paths, &roots),
+            [
+                "/home/me/Documents",
+                "/home/me/Documents/notes",
+                "/home/me/Code/api",
+                "/home/me/Code/web",
+                "/other",
+            ]
+            .map(PathBuf::from)
+        );
+    }
+}
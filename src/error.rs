@@ -5,6 +5,13 @@ pub enum CacheError {
     Write(&'static str, std::io::Error),
     Read(&'static str, std::io::Error),
 }
+impl std::error::Error for CacheError {
+    fn source(&self) -> Option<&(dyn std::error::Error + 'static)> {
+        match self {
+            Self::Write(_, err) | Self::Read(_, err) => Some(err),
+        }
+    }
+}
 impl Display for CacheError {
     fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
         match self {
@@ -21,9 +28,30 @@ pub enum Error {
     EnvError(String),
     MissingHome,
     Cache(CacheError),
+    SourceCommand(String),
+    PickerNotFound(String),
+    /// The picker exists but couldn't be started or waited on
+    PickerSpawn(String),
+    NewDir(String),
+    NotInTmux(&'static str),
+    MissingSelection(String),
+    Git(String),
+    NotUtf8(String),
+    NewSession(String),
+    /// The picker was closed without selecting anything
+    Canceled,
+    TmuxNotFound,
+    RunTmux(String),
 }
 
-impl std::error::Error for Error {}
+impl std::error::Error for Error {
+    fn source(&self) -> Option<&(dyn std::error::Error + 'static)> {
+        match self {
+            Self::Cache(e) => Some(e),
+            _ => None,
+        }
+    }
+}
 impl Display for Error {
     fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
         match self {
@@ -31,6 +59,32 @@ impl Display for Error {
             Self::EnvError(e) => write!(f, "EnvError: {e}"),
             Self::MissingHome => write!(f, "Missing 'HOME' env var"),
             Self::Cache(e) => write!(f, "Cache Error: {e}"),
+            Self::SourceCommand(e) => write!(f, "Source command failed: {e}"),
+            Self::PickerNotFound(cmd) => write!(
+                f,
+                "picker '{cmd}' not found; install it or set settings.picker"
+            ),
+            Self::PickerSpawn(e) => write!(f, "Can't run picker {e}"),
+            Self::NewDir(e) => write!(f, "Can't create directory: {e}"),
+            Self::NotInTmux(what) => write!(f, "{what} only works inside tmux"),
+            Self::MissingSelection(path) => {
+                write!(
+                    f,
+                    "'{path}' no longer exists, it was removed after the search"
+                )
+            }
+            Self::Git(e) => write!(f, "git failed: {e}"),
+            Self::NewSession(e) => write!(f, "Can't create tmux session {e}"),
+            Self::NotUtf8(path) => write!(
+                f,
+                "'{path}' is not valid UTF-8, it can't be put in a shell command"
+            ),
+            Self::Canceled => write!(f, "Nothing was selected"),
+            Self::TmuxNotFound => write!(
+                f,
+                "tmux not found; install it with your package manager and make sure it's on PATH"
+            ),
+            Self::RunTmux(e) => write!(f, "Can't run tmux: {e}"),
         }
     }
 }
@@ -39,3 +93,102 @@ impl Error {
         Self::FileError(e.into())
     }
 }
+impl From<std::io::Error> for Error {
+    fn from(e: std::io::Error) -> Self {
+        Self::FileError(e.to_string())
+    }
+}
+impl From<serde_yml::Error> for Error {
+    fn from(e: serde_yml::Error) -> Self {
+        Self::FileError(e.to_string())
+    }
+}
+
+/// Exit codes of `tms`, listed in the README
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ExitStatus {
+    Success = 0,
+    /// A config file, env var or the cache couldn't be read
+    Config = 1,
+    /// The picker was canceled
+    NoSelection = 2,
+    /// A tmux command failed or needs to run inside tmux
+    Tmux = 3,
+    /// Anything else, such as a failed source command or a missing picker
+    Failure = 4,
+}
+impl From<&Error> for ExitStatus {
+    fn from(e: &Error) -> Self {
+        match e {
+            Error::FileError(_) | Error::EnvError(_) | Error::MissingHome | Error::Cache(_) => {
+                Self::Config
+            }
+            Error::Canceled => Self::NoSelection,
+            Error::NotInTmux(_)
+            | Error::NewSession(_)
+            | Error::TmuxNotFound
+            | Error::RunTmux(_) => Self::Tmux,
+            Error::SourceCommand(_)
+            | Error::PickerNotFound(_)
+            | Error::PickerSpawn(_)
+            | Error::NewDir(_)
+            | Error::MissingSelection(_)
+            | Error::Git(_)
+            | Error::NotUtf8(_) => Self::Failure,
+        }
+    }
+}
+impl From<ExitStatus> for std::process::ExitCode {
+    fn from(status: ExitStatus) -> Self {
+        Self::from(status as u8)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn boxed_error_test() {
+        let boxed: Box<dyn std::error::Error> = Box::new(Error::MissingHome);
+        assert_eq!(boxed.to_string(), "Missing 'HOME' env var");
+        assert!(boxed.source().is_none());
+
+        let io = std::io::Error::new(std::io::ErrorKind::UnexpectedEof, "eof");
+        let boxed: Box<dyn std::error::Error> =
+            Box::new(Error::Cache(CacheError::Read("Settings", io)));
+        let cache = boxed.source().unwrap();
+        assert_eq!(cache.source().unwrap().to_string(), "eof");
+    }
+
+    #[test]
+    fn from_test() {
+        let io = std::io::Error::new(std::io::ErrorKind::NotFound, "no such file");
+        let err = Error::from(io);
+        assert!(matches!(err, Error::FileError(ref e) if e == "no such file"));
+        assert_eq!(err.to_string(), "Config file error 'no such file'");
+
+        let yml = serde_yml::from_str::<u8>("not a number").unwrap_err();
+        let message = yml.to_string();
+        assert!(matches!(Error::from(yml), Error::FileError(e) if e == message));
+    }
+
+    #[test]
+    fn exit_status_test() {
+        let status = |e: Error| ExitStatus::from(&e) as u8;
+
+        assert_eq!(status(Error::file_error("bad yaml")), 1);
+        assert_eq!(status(Error::MissingHome), 1);
+        assert_eq!(status(Error::Canceled), 2);
+        assert_eq!(status(Error::NotInTmux("rename")), 3);
+        assert_eq!(status(Error::NewSession("'api': bad name".into())), 3);
+        assert_eq!(status(Error::TmuxNotFound), 3);
+        assert_eq!(status(Error::RunTmux("permission denied".into())), 3);
+        assert_eq!(status(Error::PickerNotFound("sk".into())), 4);
+        assert_eq!(
+            status(Error::PickerSpawn("'sk': permission denied".into())),
+            4
+        );
+        assert_eq!(status(Error::Git("not a repository".into())), 4);
+    }
+}
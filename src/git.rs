@@ -0,0 +1,149 @@
+use crate::error::Error;
+use std::{
+    path::{Path, PathBuf},
+    process::Command,
+};
+
+#[derive(Debug, PartialEq, Eq)]
+pub struct Worktree {
+    pub path: PathBuf,
+    pub head: String,
+    /// Checked out branch without `refs/heads/`, `None` for a detached HEAD
+    pub branch: Option<String>,
+}
+
+impl Worktree {
+    /// The branch, or the short commit for a detached HEAD
+    pub fn label(&self) -> &str {
+        self.branch
+            .as_deref()
+            .unwrap_or_else(|| self.head.get(..7).unwrap_or(&self.head))
+    }
+}
+
+/// Parses `git worktree list --porcelain`, skipping bare repositories
+fn parse_worktrees(output: &str) -> Vec<Worktree> {
+    output
+        .split("\n\n")
+        .filter_map(|block| {
+            let mut path = None;
+            let mut head = String::new();
+            let mut branch = None;
+
+            for line in block.lines() {
+                let (key, value) = line.split_once(' ').unwrap_or((line, ""));
+                match key {
+                    "worktree" => path = Some(PathBuf::from(value)),
+                    "HEAD" => head = value.to_string(),
+                    "branch" => {
+                        branch = Some(value.strip_prefix("refs/heads/").unwrap_or(value).into())
+                    }
+                    "bare" => return None,
+                    _ => {}
+                }
+            }
+
+            path.map(|path| Worktree { path, head, branch })
+        })
+        .collect()
+}
+
+/// The branch named in a `HEAD` file, or the short commit for a detached HEAD
+fn parse_head(contents: &str) -> Option<String> {
+    let head = contents.trim();
+    if let Some(reference) = head.strip_prefix("ref: ") {
+        return Some(
+            reference
+                .strip_prefix("refs/heads/")
+                .unwrap_or(reference)
+                .into(),
+        );
+    }
+
+    (head.len() >= 7 && head.chars().all(|c| c.is_ascii_hexdigit())).then(|| head[..7].into())
+}
+
+/// Checked out branch of the repository at `repo`, read from `.git/HEAD` without running git.
+/// Worktrees and submodules, where `.git` is a file pointing at the git dir, are followed
+pub fn current_branch(repo: &Path) -> Option<String> {
+    let dot_git = repo.join(".git");
+    let git_dir = if dot_git.is_file() {
+        let contents = std::fs::read_to_string(&dot_git).ok()?;
+        repo.join(contents.trim().strip_prefix("gitdir: ")?)
+    } else {
+        dot_git
+    };
+
+    parse_head(&std::fs::read_to_string(git_dir.join("HEAD")).ok()?)
+}
+
+/// Worktrees of the repository at `repo`
+pub fn list_worktrees(repo: &Path) -> Result<Vec<Worktree>, Error> {
+    let output = Command::new("git")
+        .arg("-C")
+        .arg(repo)
+        .args(["worktree", "list", "--porcelain"])
+        .output()
+        .map_err(|e| Error::Git(e.to_string()))?;
+
+    if !output.status.success() {
+        return Err(Error::Git(
+            String::from_utf8_lossy(&output.stderr).trim().to_string(),
+        ));
+    }
+
+    Ok(parse_worktrees(&String::from_utf8_lossy(&output.stdout)))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn parse_worktrees_test() {
+        let output = "\
+worktree /code/api.git
+bare
+
+worktree /code/api
+HEAD 4f7c2a91d3e5b6f8a0c1d2e3f4a5b6c7d8e9f0a1
+branch refs/heads/main
+
+worktree /code/api-fix
+HEAD 9e8d7c6b5a4f3e2d1c0b9a8f7e6d5c4b3a2f1e0d
+branch refs/heads/fix/login
+
+worktree /code/api-old
+HEAD 0123456789abcdef0123456789abcdef01234567
+detached
+";
+
+        let worktrees = parse_worktrees(output);
+        assert_eq!(
+            worktrees
+                .iter()
+                .map(|w| (w.path.to_str().unwrap(), w.label()))
+                .collect::<Vec<_>>(),
+            [
+                ("/code/api", "main"),
+                ("/code/api-fix", "fix/login"),
+                ("/code/api-old", "0123456"),
+            ]
+        );
+        assert_eq!(worktrees[2].branch, None);
+        assert!(parse_worktrees("").is_empty());
+    }
+
+    #[test]
+    fn parse_head_test() {
+        assert_eq!(
+            parse_head("ref: refs/heads/fix/login\n").as_deref(),
+            Some("fix/login")
+        );
+        assert_eq!(
+            parse_head("4f7c2a91d3e5b6f8a0c1d2e3f4a5b6c7d8e9f0a1\n").as_deref(),
+            Some("4f7c2a9")
+        );
+        assert_eq!(parse_head(""), None);
+    }
+}
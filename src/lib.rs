@@ -1,4 +1,5 @@
 // Exported for testing only
 pub mod binary;
 pub mod config;
+pub mod discovery;
 pub mod error;
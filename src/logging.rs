@@ -0,0 +1,196 @@
+use log::{Level, LevelFilter, Log, Metadata, Record};
+use std::{io::Write, sync::Mutex};
+
+/// Writes log lines to stderr, prefixed with the level unless it's `info`
+struct Logger<W> {
+    out: Mutex<W>,
+}
+
+impl<W: Write + Send> Log for Logger<W> {
+    fn enabled(&self, metadata: &Metadata) -> bool {
+        metadata.level() <= log::max_level()
+    }
+
+    fn log(&self, record: &Record) {
+        if !self.enabled(record.metadata()) {
+            return;
+        }
+
+        let mut out = self.out.lock().unwrap_or_else(|e| e.into_inner());
+        let _ = match record.level() {
+            Level::Info => writeln!(out, "{}", record.args()),
+            level => writeln!(out, "{}: {}", level.as_str().to_lowercase(), record.args()),
+        };
+    }
+
+    fn flush(&self) {}
+}
+
+/// How much is logged, set by `--quiet` and `--verbose`
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum Verbosity {
+    /// Only errors
+    Quiet,
+    #[default]
+    Normal,
+    /// Everything down to `debug`
+    Verbose,
+}
+
+impl Verbosity {
+    pub fn from_flags(quiet: bool, verbose: bool) -> Self {
+        match (quiet, verbose) {
+            (true, _) => Self::Quiet,
+            (_, true) => Self::Verbose,
+            _ => Self::Normal,
+        }
+    }
+}
+
+/// `--quiet` and `--verbose` win over `RUST_LOG`, which wins over the `log_level` setting.
+/// Unset or invalid levels fall back to `info`
+pub fn level(verbosity: Verbosity, env: Option<&str>, setting: Option<&str>) -> LevelFilter {
+    match verbosity {
+        Verbosity::Quiet => LevelFilter::Error,
+        Verbosity::Verbose => LevelFilter::Debug,
+        Verbosity::Normal => env
+            .and_then(|l| l.parse().ok())
+            .or_else(|| setting.and_then(|l| l.parse().ok()))
+            .unwrap_or(LevelFilter::Info),
+    }
+}
+
+fn init_with<W: Write + Send + 'static>(out: W, level: LevelFilter) {
+    let logger = Box::leak(Box::new(Logger {
+        out: Mutex::new(out),
+    }));
+
+    // Only fails if a logger is already installed, which then keeps logging
+    let _ = log::set_logger(logger);
+    log::set_max_level(level);
+}
+
+pub fn init(level: LevelFilter) {
+    init_with(std::io::stderr(), level);
+}
+
+pub fn set_level(level: LevelFilter) {
+    log::set_max_level(level);
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::config::{Config, SearchPath, Settings};
+    use crate::tmux::{CommandRunner, Tmux};
+    use std::{
+        ffi::OsStr,
+        os::unix::process::ExitStatusExt,
+        process::{ExitStatus, Output},
+        sync::{Arc, OnceLock},
+    };
+
+    #[derive(Clone, Default)]
+    struct SharedBuffer(Arc<Mutex<Vec<u8>>>);
+
+    impl Write for SharedBuffer {
+        fn write(&mut self, buf: &[u8]) -> std::io::Result<usize> {
+            self.0.lock().unwrap().write(buf)
+        }
+
+        fn flush(&mut self) -> std::io::Result<()> {
+            Ok(())
+        }
+    }
+
+    /// Runs `f` with logging at `level`, returning what it logged. The logger is global, so
+    /// tests share one buffer and take turns
+    fn capture(level: LevelFilter, f: impl FnOnce()) -> String {
+        static BUFFER: OnceLock<SharedBuffer> = OnceLock::new();
+        static TURN: Mutex<()> = Mutex::new(());
+
+        let _turn = TURN.lock().unwrap_or_else(|e| e.into_inner());
+        let buffer = BUFFER.get_or_init(|| {
+            let buffer = SharedBuffer::default();
+            init_with(buffer.clone(), level);
+            buffer
+        });
+        set_level(level);
+        buffer.0.lock().unwrap().clear();
+
+        f();
+
+        String::from_utf8(buffer.0.lock().unwrap().clone()).unwrap()
+    }
+
+    struct NoopRunner;
+
+    impl CommandRunner for NoopRunner {
+        fn output(&self, _program: &str, _args: &[&OsStr]) -> std::io::Result<Output> {
+            Ok(Output {
+                status: ExitStatus::from_raw(0),
+                stdout: vec![],
+                stderr: vec![],
+            })
+        }
+
+        fn spawn(&self, _program: &str, _args: &[&OsStr], _wait: bool) -> std::io::Result<()> {
+            Ok(())
+        }
+    }
+
+    #[test]
+    fn level_test() {
+        let normal = Verbosity::Normal;
+        assert_eq!(
+            level(normal, Some("debug"), Some("warn")),
+            LevelFilter::Debug
+        );
+        assert_eq!(level(normal, None, Some("warn")), LevelFilter::Warn);
+        assert_eq!(level(normal, Some("nonsense"), None), LevelFilter::Info);
+        assert_eq!(
+            level(Verbosity::Verbose, None, Some("warn")),
+            LevelFilter::Debug
+        );
+    }
+
+    #[test]
+    fn quiet_test() {
+        let dir = tempfile::tempdir().unwrap();
+        let code = dir.path().join("code");
+        std::fs::create_dir(&code).unwrap();
+        std::os::unix::fs::symlink(&code, code.join("loop")).unwrap();
+        let config = Config {
+            settings: Settings {
+                default_depth: 3,
+                follow_symlinks: Some(true),
+                ..Default::default()
+            },
+            paths: vec![SearchPath::Simple(code.display().to_string())],
+        };
+
+        let run = |verbosity| {
+            capture(level(verbosity, Some("debug"), None), || {
+                crate::discovery::find_dirs(&config).unwrap();
+                log::error!("quiet-test failed");
+            })
+        };
+        let skipped = format!("warn: skipping '{}'", code.join("loop").display());
+
+        let output = run(Verbosity::Normal);
+        assert!(output.contains(&skipped), "{output}");
+
+        let output = run(Verbosity::Quiet);
+        assert!(!output.contains(&skipped), "{output}");
+        assert!(output.contains("error: quiet-test failed"), "{output}");
+    }
+
+    #[test]
+    fn debug_smoke_test() {
+        let output = capture(LevelFilter::Debug, || {
+            Tmux::with_runner(&Settings::default(), NoopRunner).has_session("smoke-test");
+        });
+
+        assert!(output.contains("debug: Running tmux has-session -t smoke-test"));
+    }
+}
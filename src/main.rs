@@ -1,76 +1,134 @@
 mod config;
+mod discovery;
 mod error;
-use config::{Config, Settings};
+use config::{
+    Config, InsideAction, LayoutWindow, Mode, ProjectSettings, SearchPath, Settings, source_root,
+};
 mod binary;
+mod git;
+mod logging;
+mod name;
+mod picker;
 mod tmux;
 
-use crate::config::CacheStatus;
+use crate::{
+    config::{CacheMode, CacheStatus},
+    error::{Error, ExitStatus},
+};
 use clap::{Parser, Subcommand};
+use picker::{PickerOptions, run_finder, run_picker};
 use std::{
+    ffi::OsString,
     io::Write,
     path::{Path, PathBuf},
-    process::{Command, Stdio},
+    time::{Duration, SystemTime},
 };
+use tmux::{CommandRunner, Tmux};
+
+/// `selected` made absolute, tmux would resolve relative paths against its own working directory.
+/// Symlinks are resolved too when `name_from_realpath` is set
+fn resolve_selected(settings: &Settings, selected: PathBuf) -> PathBuf {
+    if settings.name_from_realpath.unwrap_or(false)
+        && let Ok(real) = selected.canonicalize()
+    {
+        return real;
+    }
 
-fn run_finder(Settings { picker, .. }: &Settings, paths: &[PathBuf]) -> Option<PathBuf> {
-    let picker = picker.as_deref().unwrap_or("fzf-tmux -p 50%");
+    std::path::absolute(&selected).unwrap_or(selected)
+}
 
-    let paths = paths.iter().filter_map(|p| p.to_str());
+/// Writes the absolute `selected` paths to `output`, one per line, replacing files atomically so readers never
+/// see a partial path. Named pipes are written to directly
+fn write_selection(output: &Path, selected: &[PathBuf]) -> std::io::Result<()> {
+    use std::os::unix::fs::FileTypeExt;
 
-    let mut paths_input = String::new();
-    for p in paths {
-        paths_input.push_str(p);
-        paths_input.push('\n');
+    let mut contents = vec![];
+    for path in selected {
+        let path = std::path::absolute(path)?;
+        contents.extend(path.into_os_string().into_encoded_bytes());
+        contents.push(b'\n');
     }
 
-    let (cmd, args) = picker
-        .split_once(' ')
-        .map(|(cmd, args)| {
-            let args = args.split(' ').collect::<Vec<_>>();
-            (cmd, args)
-        })
-        .unwrap_or((picker, vec![]));
-
-    let mut proc = Command::new(cmd)
-        .args(args)
-        .stdin(Stdio::piped())
-        .stdout(Stdio::piped())
-        .spawn()
-        .unwrap_or_else(|e| panic!("Failed to spawn picker command \"{picker}\", {e}"));
-
-    proc.stdin
-        .as_mut()
-        .expect("Failed to get stdin")
-        .write_all(paths_input.as_bytes())
-        .expect("Failed to write to stdin");
-
-    let res = proc
-        .wait_with_output()
-        .expect("Failed to run picker command");
-
-    if res.status.success() {
-        let s = String::from_utf8(res.stdout).expect("Picker output is not UTF-8");
-        let s = &s[..s.len() - 1]; // Strip ending new line
-        let path = PathBuf::from(s);
-        Some(path)
-    } else {
-        None
+    if std::fs::metadata(output).is_ok_and(|m| m.file_type().is_fifo()) {
+        return std::fs::write(output, contents);
     }
+
+    let mut tmp = output.as_os_str().to_owned();
+    tmp.push(".tmp");
+    std::fs::write(&tmp, contents)?;
+    std::fs::rename(tmp, output)
 }
 
-fn get_dir_name(dir: &Path) -> String {
-    let s = dir
-        .file_name()
-        .and_then(|s| s.to_str())
-        .expect("Dir is not valid UTF-8");
+/// The selection may have been removed while the picker was open
+fn check_selected(selected: &Path) -> Result<(), Error> {
+    if selected.exists() && session_dir(selected).is_dir() {
+        Ok(())
+    } else {
+        Err(Error::MissingSelection(selected.display().to_string()))
+    }
+}
 
-    s.replace('.', "_")
+/// Directory a session is started in, files open in their parent directory
+fn session_dir(selected: &Path) -> &Path {
+    match selected.parent() {
+        Some(parent) if selected.is_file() => parent,
+        _ => selected,
+    }
 }
 
 #[derive(Debug, Subcommand)]
 enum ArgCommand {
     /// Create new directory in selected path
-    New { dir_name: String },
+    New {
+        dir_name: String,
+
+        /// Create it in `settings.new_root` or the first search path instead of picking one
+        #[arg(long)]
+        root: bool,
+    },
+    /// Pick from the open sessions, most recently used first
+    Switch,
+    /// Pick from the most recently modified directories
+    Recent {
+        #[arg(default_value_t = 10)]
+        count: usize,
+    },
+    /// Pick from the directories not modified in the last `days` days
+    Stale {
+        #[arg(long, default_value_t = 30)]
+        days: u64,
+
+        /// Kill the selected directory's session instead of opening it
+        #[arg(long)]
+        kill_session: bool,
+    },
+    /// Print the session name tms would use for `path`
+    Name { path: PathBuf },
+    /// Rename the current session after its directory
+    Rename,
+    /// Open the scratch session, starting it in `settings.scratch_path` if it isn't running
+    Scratch,
+    /// Pick a repository, then one of its git worktrees to open as `repo@branch`
+    Worktree,
+    /// Print the open sessions and their directories
+    Sessions {
+        /// Only print the sessions whose directory was moved or removed
+        #[arg(long)]
+        check: bool,
+    },
+    /// Manage the config file
+    Config {
+        #[command(subcommand)]
+        command: ConfigCommand,
+    },
+}
+
+#[derive(Debug, Subcommand)]
+enum ConfigCommand {
+    /// Open the config file in `$EDITOR`, creating it if it doesn't exist
+    Edit,
+    /// Print the config in effect, after merging the layers and applying overrides, as YAML
+    Show,
 }
 
 #[derive(Debug, Parser)]
@@ -78,67 +136,920 @@ enum ArgCommand {
 struct Args {
     #[command(subcommand)]
     command: Option<ArgCommand>,
+
+    /// Pre-fill the picker query (fzf pickers only)
+    #[arg(short, long, visible_alias = "pick")]
+    query: Option<String>,
+
+    /// With `--query`, attach to the only open session whose name contains it instead of picking
+    #[arg(long, requires = "query")]
+    switch: bool,
+
+    /// Also write the selected path to this file or named pipe
+    #[arg(long)]
+    output_file: Option<PathBuf>,
+
+    /// Select several directories and start a session for each (fzf pickers only)
+    #[arg(long)]
+    multi: bool,
+
+    /// Which session to attach to when several were selected
+    #[arg(long, value_enum, default_value_t = AttachTarget::Last)]
+    attach: AttachTarget,
+
+    /// Print the discovered directories instead of opening the picker
+    #[arg(long)]
+    list: bool,
+
+    /// Print how many directories were discovered instead of opening the picker
+    #[arg(long, conflicts_with = "list")]
+    count: bool,
+
+    /// How `--list` prints the directories
+    #[arg(long, value_enum, default_value_t = ListFormat::Plain, requires = "list")]
+    format: ListFormat,
+
+    /// Open the selected directory in a popup shell instead of a session (inside tmux only)
+    #[arg(long)]
+    popup: bool,
+
+    /// Print each tmux command to stderr before running it
+    #[arg(long)]
+    show_commands: bool,
+
+    /// Picker command for this run, overrides `TMS_PICKER` and `settings.picker`
+    #[arg(long, visible_alias = "picker-cmd")]
+    picker: Option<String>,
+
+    /// Read the config files instead of the cache, without updating it
+    #[arg(long, conflicts_with = "refresh")]
+    no_cache: bool,
+
+    /// Read the config files instead of the cache and rewrite the cache
+    #[arg(long)]
+    refresh: bool,
+
+    /// Active profile, shown in `settings.picker_header` and prefixing session names with
+    /// `prefix_with_profile`. Defaults to `TMS_PROFILE`
+    #[arg(long)]
+    profile: Option<String>,
+
+    /// Only log errors, hiding warnings such as skipped paths
+    #[arg(long, conflicts_with = "verbose")]
+    quiet: bool,
+
+    /// Log everything down to debug messages
+    #[arg(long)]
+    verbose: bool,
+}
+
+/// The `--picker` flag beats `TMS_PICKER`, which beats `settings.picker`
+fn resolve_picker(flag: Option<&str>, env: Option<&str>, setting: Option<&str>) -> Option<String> {
+    flag.or(env).or(setting).map(str::to_string)
+}
+
+/// Output format of `--list`
+#[derive(Debug, Clone, Copy, PartialEq, Eq, clap::ValueEnum)]
+enum ListFormat {
+    /// One path per line
+    Plain,
+    /// rofi `-dmenu` entries, with a `folder` or `git` icon when `display_icons` is on
+    Rofi,
+}
+
+/// A rofi `-dmenu` line for `path`, the icon is passed as row metadata
+fn rofi_entry(path: &str, icon: Option<&str>) -> String {
+    match icon {
+        Some(icon) => format!("{path}\0icon\x1f{icon}"),
+        None => path.to_string(),
+    }
+}
+
+/// Writes one path per line in `format`, skipping the same non UTF-8 paths as the picker
+fn write_list<W: Write>(
+    writer: &mut W,
+    paths: &[PathBuf],
+    format: ListFormat,
+    icons: bool,
+) -> std::io::Result<()> {
+    for p in paths.iter().filter_map(|p| p.to_str()) {
+        match format {
+            ListFormat::Plain => writeln!(writer, "{p}")?,
+            ListFormat::Rofi => {
+                let icon = icons.then(|| {
+                    if Path::new(p).join(".git").exists() {
+                        "git"
+                    } else {
+                        "folder"
+                    }
+                });
+                writeln!(writer, "{}", rofi_entry(p, icon))?
+            }
+        }
+    }
+
+    Ok(())
+}
+
+/// Writes the number of `paths` that `write_list` would print
+fn write_count<W: Write>(writer: &mut W, paths: &[PathBuf]) -> std::io::Result<()> {
+    writeln!(
+        writer,
+        "{}",
+        paths.iter().filter(|p| p.to_str().is_some()).count()
+    )
 }
 
-fn new_session(dir_name: &str, path_str: &str) {
-    if !tmux::has_session(dir_name) {
-        tmux::new_session(dir_name, path_str);
+/// Session name for `tms name`, without asking tmux which names are taken
+fn name_for_path(settings: &Settings, profile: Option<&str>, path: &Path) -> String {
+    let path = std::path::absolute(path).unwrap_or_else(|_| path.to_path_buf());
+    let name = name::session_name(settings, &path, session_dir(&path));
+
+    name::with_profile(settings, profile, name::non_empty_name(name, |_| false))
+}
+
+/// Whether one of `sessions` named `name` was started somewhere other than `path`
+fn taken_elsewhere(sessions: &[tmux::Session], name: &str, path: &Path) -> bool {
+    sessions
+        .iter()
+        .any(|s| s.name == name && Path::new(&s.path) != path)
+}
+
+/// The only session whose name contains `query`, ignoring case
+fn unique_session_match(sessions: &[tmux::Session], query: &str) -> Option<String> {
+    let query = query.to_lowercase();
+    let mut matches = sessions
+        .iter()
+        .filter(|s| s.name.to_lowercase().contains(&query));
+
+    match (matches.next(), matches.next()) {
+        (Some(session), None) => Some(session.name.clone()),
+        _ => None,
     }
+}
 
-    if std::env::var("TMUX").is_ok() {
-        tmux::switch(dir_name);
+/// The name `session` should have, if it isn't already called that
+fn renamed(settings: &Settings, profile: Option<&str>, session: &tmux::Session) -> Option<String> {
+    let name = name_for_path(settings, profile, Path::new(&session.path));
+    Some(name).filter(|n| *n != session.name)
+}
+
+fn rename_current(settings: &Settings, profile: Option<&str>, tmux: &Tmux) -> Result<(), Error> {
+    let current = tmux
+        .current_session()
+        .ok_or(Error::NotInTmux("tms rename"))?;
+    let session = tmux
+        .list_sessions()?
+        .into_iter()
+        .find(|s| s.name == current);
+
+    if let Some(session) = session
+        && let Some(name) = renamed(settings, profile, &session)
+    {
+        tmux.rename_session(&session.name, &name);
+        log::info!("Renamed '{}' to '{name}'", session.name);
+    }
+
+    Ok(())
+}
+
+/// Why a session started in `path` no longer has a directory there, if it doesn't
+fn moved_session(path: &Path) -> Option<&'static str> {
+    match path.metadata() {
+        Ok(metadata) if metadata.is_dir() => None,
+        Ok(_) => Some("not a directory"),
+        Err(_) => Some("missing"),
+    }
+}
+
+fn list_sessions(tmux: &Tmux, check: bool) -> Result<(), Error> {
+    for session in tmux.list_sessions()? {
+        match moved_session(Path::new(&session.path)) {
+            Some(problem) => println!("{}\t{}\t{problem}", session.name, session.path),
+            None if !check => println!("{}\t{}", session.name, session.path),
+            None => {}
+        }
+    }
+
+    Ok(())
+}
+
+fn current_session_path(tmux: &Tmux) -> Option<PathBuf> {
+    let current = tmux.current_session()?;
+    tmux.list_sessions()
+        .ok()?
+        .into_iter()
+        .find(|s| s.name == current)
+        .map(|s| PathBuf::from(s.path))
+}
+
+/// `dirs` without `current`
+fn exclude_dir(dirs: Vec<PathBuf>, current: &Path) -> Vec<PathBuf> {
+    dirs.into_iter().filter(|d| d != current).collect()
+}
+
+/// Index of the deepest of `dirs` containing `cwd`
+fn match_cwd(dirs: &[PathBuf], cwd: &Path) -> Option<usize> {
+    dirs.iter()
+        .enumerate()
+        .filter(|(_, dir)| cwd.starts_with(dir))
+        .max_by_key(|(_, dir)| dir.components().count())
+        .map(|(i, _)| i)
+}
+
+fn modified(path: &Path) -> Option<SystemTime> {
+    path.metadata().and_then(|m| m.modified()).ok()
+}
+
+/// The `count` most recently modified paths, newest first, equally old paths by name
+fn most_recent(paths: Vec<PathBuf>, count: usize) -> Vec<PathBuf> {
+    let mut paths = paths
+        .into_iter()
+        .map(|p| (modified(&p), p))
+        .collect::<Vec<_>>();
+
+    // Ties go alphabetically so the order doesn't depend on how the paths were found
+    paths.sort_by(|(a_modified, a), (b_modified, b)| b_modified.cmp(a_modified).then(a.cmp(b)));
+    paths.into_iter().take(count).map(|(_, p)| p).collect()
+}
+
+/// Paths last modified more than `days` days before `now`
+fn stale(paths: Vec<PathBuf>, now: SystemTime, days: u64) -> Vec<PathBuf> {
+    // A cutoff before any representable time leaves nothing stale
+    let Some(cutoff) = days
+        .checked_mul(24 * 60 * 60)
+        .and_then(|secs| now.checked_sub(Duration::from_secs(secs)))
+    else {
+        return vec![];
+    };
+    paths
+        .into_iter()
+        .filter(|p| modified(p).is_some_and(|m| m < cutoff))
+        .collect()
+}
+
+/// Command for the first window, `None` leaves it to tmux's `default-shell`
+fn session_shell(shell: Option<&str>, login: bool, env_shell: Option<&str>) -> Option<String> {
+    match (shell, login) {
+        (Some(shell), true) => Some(format!("{shell} -l")),
+        (Some(shell), false) => Some(shell.to_string()),
+        (None, true) => env_shell.map(|shell| format!("{shell} -l")),
+        (None, false) => None,
+    }
+}
+
+/// Runs `shell` with the environment direnv loads for `path`. `shell` is a command line
+/// like `settings.shell` and is passed on as is
+fn direnv_command(path: &str, shell: &str) -> String {
+    format!("direnv exec {} {shell}", shell_words::quote(path))
+}
+
+/// `template` with each `{path}` replaced by `path`, which doesn't have to be UTF-8
+fn fill_path(template: &str, path: &Path) -> OsString {
+    let mut filled = OsString::new();
+    for (i, part) in template.split("{path}").enumerate() {
+        if i > 0 {
+            filled.push(path);
+        }
+        filled.push(part);
+    }
+
+    filled
+}
+
+fn new_session<R: CommandRunner>(
+    settings: &Settings,
+    tmux: &Tmux<R>,
+    dir_name: &str,
+    path: &Path,
+    selected: &Path,
+) -> Result<(), Error> {
+    let name = start_session(settings, tmux, dir_name, path, selected)?;
+    attach_session(settings, tmux, &name)
+}
+
+/// Creates the session unless it's already running, returning its name
+fn start_session<R: CommandRunner>(
+    settings: &Settings,
+    tmux: &Tmux<R>,
+    dir_name: &str,
+    path: &Path,
+    selected: &Path,
+) -> Result<String, Error> {
+    debug_assert!(path.is_absolute(), "relative session path");
+
+    // Reuse a session whose name only differs in case or punctuation
+    let sessions = tmux.list_sessions()?;
+    let dir_name = name::matching_session(sessions.iter().map(|s| s.name.as_str()), dir_name)
+        .unwrap_or(dir_name);
+
+    // Skip names held by sessions in other directories
+    let dir_name = if settings.auto_suffix.unwrap_or(false) {
+        name::next_free_name(dir_name, |n| taken_elsewhere(&sessions, n, path))
     } else {
-        tmux::attach(dir_name);
+        dir_name.to_string()
+    };
+    let dir_name = dir_name.as_str();
+
+    if !tmux.has_session(dir_name) {
+        let mut windows = settings
+            .layout
+            .iter()
+            .flatten()
+            .map(|w| LayoutWindow {
+                name: w.name.replace("{name}", dir_name),
+                command: w.command.clone(),
+            })
+            .collect::<Vec<_>>();
+        if windows.is_empty()
+            && let Some(window_name) = &settings.window_name
+        {
+            windows.push(LayoutWindow {
+                name: window_name.replace("{name}", dir_name),
+                command: None,
+            });
+        }
+
+        let env_shell = std::env::var("SHELL").ok();
+        let mut shell = session_shell(
+            settings.shell.as_deref(),
+            settings.shell_login.unwrap_or(false),
+            env_shell.as_deref(),
+        );
+        let env = settings
+            .env
+            .iter()
+            .flatten()
+            .map(|(key, value)| format!("{key}={value}"))
+            .collect::<Vec<_>>();
+        if settings.use_direnv.unwrap_or(false) {
+            // The path goes into a shell command, which has to be a string
+            let path_str = path
+                .to_str()
+                .ok_or_else(|| Error::NotUtf8(path.to_string_lossy().into_owned()))?;
+            let inner = shell.or(env_shell).unwrap_or_else(|| "sh".into());
+            shell = Some(direnv_command(path_str, &inner));
+        }
+        let created =
+            tmux.new_session_with_windows(dir_name, path, &windows, &env, shell.as_deref())?;
+
+        if created {
+            for option in settings.session_options.iter().flatten() {
+                tmux.set_option(dir_name, option);
+            }
+            if let Some(on_create) = &settings.on_create {
+                if let Some(delay) = settings.on_create_delay_ms {
+                    std::thread::sleep(Duration::from_millis(delay));
+                }
+                tmux.send_keys(dir_name, fill_path(on_create, selected));
+            }
+        }
+    }
+
+    Ok(dir_name.to_string())
+}
+
+/// Which of several selected sessions to attach to
+#[derive(Debug, Clone, Copy, PartialEq, Eq, clap::ValueEnum)]
+enum AttachTarget {
+    First,
+    Last,
+    None,
+}
+
+fn attach_target<T>(started: &[T], target: AttachTarget) -> Option<&T> {
+    match target {
+        AttachTarget::First => started.first(),
+        AttachTarget::Last => started.last(),
+        AttachTarget::None => None,
     }
 }
 
-fn run_command(config: &Config, command: Option<&ArgCommand>) {
-    let paths = config.find_dirs().unwrap();
+#[derive(Debug, PartialEq, Eq)]
+enum AttachAction {
+    Attach,
+    Switch,
+    NewWindow,
+}
+
+/// Outside tmux we always attach, inside it `inside_tmux_action` decides
+fn attach_action(inside_tmux: bool, setting: Option<InsideAction>) -> AttachAction {
+    if !inside_tmux {
+        return AttachAction::Attach;
+    }
 
-    let selected_path = if let Some(path) = run_finder(&config.settings, &paths) {
-        path
+    match setting.unwrap_or_default() {
+        InsideAction::Switch => AttachAction::Switch,
+        InsideAction::Attach => AttachAction::Attach,
+        InsideAction::NewWindowToSession => AttachAction::NewWindow,
+    }
+}
+
+fn attach_session<R: CommandRunner>(
+    settings: &Settings,
+    tmux: &Tmux<R>,
+    name: &str,
+) -> Result<(), Error> {
+    let inside_tmux = std::env::var("TMUX").is_ok();
+    let no_steal = settings.no_steal.unwrap_or(false);
+
+    let action = if no_steal && inside_tmux {
+        AttachAction::Switch
     } else {
-        // Exit if picker is canceled
-        return;
+        attach_action(inside_tmux, settings.inside_tmux_action)
     };
+    if no_steal && action == AttachAction::Attach && tmux.session_attached(name) {
+        log::warn!("session '{name}' is already attached by another client");
+    }
 
-    let path_str = selected_path.to_str().expect("Selected path is not UTF-8");
-    let dir_name = get_dir_name(&selected_path);
+    match action {
+        AttachAction::Attach => tmux.attach(name, settings.terminal.as_deref())?,
+        AttachAction::Switch => tmux.switch(name),
+        AttachAction::NewWindow => tmux.attach_in_window(name),
+    }
 
-    match command {
-        Some(ArgCommand::New {
-            dir_name: new_dir_name,
+    Ok(())
+}
+
+const SCRATCH_SESSION: &str = "scratch";
+
+fn open_scratch(settings: &Settings, tmux: &Tmux) -> Result<(), Error> {
+    let path =
+        SearchPath::Simple(settings.scratch_path.clone().unwrap_or_else(|| "~".into())).expand()?;
+    let path = path.path();
+
+    if tmux.ensure_session(SCRATCH_SESSION, path)? {
+        log::info!("Created scratch session in {}", path.display());
+    }
+    attach_session(settings, tmux, SCRATCH_SESSION)
+}
+
+fn open_popup(settings: &Settings, tmux: &Tmux, path: &Path) -> Result<(), Error> {
+    if std::env::var("TMUX").is_err() {
+        return Err(Error::NotInTmux("--popup"));
+    }
+
+    let shell = settings
+        .shell
+        .clone()
+        .or_else(|| std::env::var("SHELL").ok())
+        .unwrap_or_else(|| "sh".into());
+    tmux.popup(path, &shell);
+
+    Ok(())
+}
+
+/// Opens the config file in `$EDITOR` or `$VISUAL` and checks the config it's merged into
+/// still parses
+fn edit_config() -> Result<(), Error> {
+    let home = std::env::var("HOME").map_err(|_| Error::MissingHome)?;
+    let env_config = std::env::var("TMS_CONFIG").ok();
+    let path = Config::edit_path(Path::new(&home), env_config.clone());
+    if !path.exists() {
+        if let Some(parent) = path.parent() {
+            std::fs::create_dir_all(parent)?;
+        }
+        std::fs::write(&path, Config::TEMPLATE)?;
+    }
+
+    let editor = std::env::var("EDITOR")
+        .or_else(|_| std::env::var("VISUAL"))
+        .unwrap_or_else(|_| "vi".into());
+    // Through the shell, so editors with arguments like `code -w` work
+    let status = std::process::Command::new("sh")
+        .arg("-c")
+        .arg(format!("{editor} \"$1\""))
+        .arg("sh")
+        .arg(&path)
+        .status()?;
+    if !status.success() {
+        return Err(Error::file_error(format!("editor '{editor}' failed")));
+    }
+
+    // Settings like exclude_regex are only checked once all layers are merged
+    Config::load_layered(&Config::layers(Path::new(&home), env_config)?).map(|_| ())
+}
+
+/// `config` as YAML, with its search paths expanded
+fn effective_config(config: &Config) -> Result<String, Error> {
+    let resolved = Config {
+        settings: config.settings.clone(),
+        paths: discovery::roots(config),
+    };
+
+    Ok(serde_yml::to_string(&resolved)?)
+}
+
+/// Lets the user pick one of the worktrees of `repo`
+fn pick_worktree(settings: &Settings, repo: &Path) -> Result<Option<git::Worktree>, Error> {
+    let mut worktrees = git::list_worktrees(repo)?;
+    let lines = worktrees
+        .iter()
+        .map(|w| format!("{} {}", w.label(), w.path.display()))
+        .collect::<Vec<_>>();
+
+    let selected = run_picker(settings, &PickerOptions::default(), &lines)?;
+    Ok(selected
+        .and_then(|s| lines.iter().position(|l| *l == s))
+        .map(|i| worktrees.swap_remove(i)))
+}
+
+fn switch_session(
+    settings: &Settings,
+    tmux: &Tmux,
+    picker_options: &PickerOptions,
+) -> Result<(), Error> {
+    let sessions = tmux.list_sessions()?;
+    let names = sessions.iter().map(|s| s.name.as_str());
+
+    let name = run_picker(settings, picker_options, names)?.ok_or(Error::Canceled)?;
+    attach_session(settings, tmux, &name)
+}
+
+/// Path for a new directory `name` in `parent`, refusing anything but a plain
+/// directory name and existing directories that aren't empty
+fn new_dir_path(parent: &Path, name: &str) -> Result<PathBuf, Error> {
+    if name.is_empty() || name == "." || name == ".." || name.contains('/') {
+        return Err(Error::NewDir(format!("invalid directory name '{name}'")));
+    }
+
+    let path = parent.join(name);
+    if path.exists() {
+        let is_empty = path
+            .read_dir()
+            .map(|mut entries| entries.next().is_none())
+            .unwrap_or(false);
+
+        if !is_empty {
+            return Err(Error::NewDir(format!(
+                "'{}' already exists and is not an empty directory",
+                path.display()
+            )));
+        }
+    }
+
+    Ok(path)
+}
+
+/// Root for `tms new --root`
+fn new_dir_root(config: &Config) -> Result<PathBuf, Error> {
+    let root = match &config.settings.new_root {
+        Some(root) => SearchPath::Simple(root.clone()).expand()?,
+        None => config
+            .paths
+            .first()
+            .ok_or_else(|| Error::NewDir("no search paths configured".into()))?
+            .expand()?,
+    };
+
+    Ok(root.path().to_path_buf())
+}
+
+fn create_new_dir(
+    settings: &Settings,
+    tmux: &Tmux,
+    parent: &Path,
+    name: &str,
+) -> Result<(), Error> {
+    let new_path = new_dir_path(parent, name)?;
+    let new_path = std::path::absolute(&new_path).unwrap_or(new_path);
+
+    std::fs::create_dir_all(&new_path)
+        .map_err(|e| Error::NewDir(format!("failed to create '{}': {e}", new_path.display())))?;
+    new_session(settings, tmux, name, &new_path, &new_path)?;
+
+    log::info!("Created {}", new_path.display());
+    Ok(())
+}
+
+fn run_command(config: &Config, args: &Args) -> Result<(), Error> {
+    let tmux = Tmux::new(&config.settings).show_commands(args.show_commands);
+    let profile = args
+        .profile
+        .clone()
+        .or_else(|| std::env::var("TMS_PROFILE").ok());
+    let picker_options = PickerOptions {
+        query: args.query.as_deref(),
+        multi: args.multi,
+        profile: profile.as_deref(),
+        ..Default::default()
+    };
+
+    match &args.command {
+        Some(ArgCommand::Switch) => {
+            return switch_session(&config.settings, &tmux, &picker_options);
+        }
+        Some(ArgCommand::Name { path }) => {
+            println!(
+                "{}",
+                name_for_path(&config.settings, profile.as_deref(), path)
+            );
+            return Ok(());
+        }
+        Some(ArgCommand::Rename) => {
+            return rename_current(&config.settings, profile.as_deref(), &tmux);
+        }
+        Some(ArgCommand::Sessions { check }) => {
+            list_sessions(&tmux, *check)?;
+            return Ok(());
+        }
+        Some(ArgCommand::Scratch) => return open_scratch(&config.settings, &tmux),
+        Some(ArgCommand::Config {
+            command: ConfigCommand::Show,
         }) => {
-            let new_path = PathBuf::from(path_str).join(new_dir_name.as_str());
-            let new_path_str = new_path.to_str().expect("New path is not UTF-8");
+            print!("{}", effective_config(config)?);
+            return Ok(());
+        }
+        Some(ArgCommand::New {
+            dir_name,
+            root: true,
+        }) => return create_new_dir(&config.settings, &tmux, &new_dir_root(config)?, dir_name),
+        _ => {}
+    }
+
+    if args.switch
+        && let Some(query) = &args.query
+    {
+        let sessions = tmux.list_sessions()?;
+        if let Some(name) = unique_session_match(&sessions, query) {
+            return attach_session(&config.settings, &tmux, &name);
+        }
+    }
+
+    let mut paths = discovery::find_dirs(config)?;
+    match &args.command {
+        Some(ArgCommand::Recent { count }) => paths = most_recent(paths, *count),
+        Some(ArgCommand::Stale { days, .. }) => paths = stale(paths, SystemTime::now(), *days),
+        _ => {}
+    }
+    if config.settings.exclude_current.unwrap_or(false)
+        && std::env::var("TMUX").is_ok()
+        && let Some(current) = current_session_path(&tmux)
+    {
+        paths = exclude_dir(paths, &current);
+    }
+
+    if args.list {
+        write_list(
+            &mut std::io::stdout().lock(),
+            &paths,
+            args.format,
+            config.settings.display_icons.unwrap_or(false),
+        )
+        .expect("Failed to write to stdout");
+        return Ok(());
+    }
+    if args.count {
+        write_count(&mut std::io::stdout().lock(), &paths).expect("Failed to write to stdout");
+        return Ok(());
+    }
+
+    let picker_options = PickerOptions {
+        select: config
+            .settings
+            .preselect_cwd
+            .unwrap_or(false)
+            .then(|| std::env::current_dir().ok())
+            .flatten()
+            .and_then(|cwd| match_cwd(&paths, &cwd)),
+        ..picker_options
+    };
 
-            std::fs::create_dir_all(&new_path).expect("failed to create new directory");
-            new_session(new_dir_name, new_path_str);
+    let roots = discovery::roots(config);
+    let selected = retry_once(config.settings.retry_on_cancel.unwrap_or(false), || {
+        run_finder(&config.settings, &picker_options, &paths, &roots)
+    })?;
+    if selected.is_empty() {
+        return Err(Error::Canceled);
+    }
+
+    let selected = selected
+        .into_iter()
+        .map(|path| {
+            check_selected(&path)?;
+            Ok(resolve_selected(&config.settings, path))
+        })
+        .collect::<Result<Vec<_>, Error>>()?;
+    if let Some(output_file) = &args.output_file {
+        write_selection(output_file, &selected).map_err(|e| {
+            Error::file_error(format!("Can't write '{}': {e}", output_file.display()))
+        })?;
+    }
+
+    let sessions = tmux.list_sessions()?;
+    let mut started = vec![];
+    for selected_path in &selected {
+        let session_path = session_dir(selected_path);
+        // Reselecting the directory reuses its fallback session instead of adding another
+        let dir_name = name::non_empty_name(
+            name::session_name(&config.settings, selected_path, session_path),
+            |n| taken_elsewhere(&sessions, n, session_path),
+        );
+        let dir_name = name::with_profile(&config.settings, profile.as_deref(), dir_name);
+        let settings = session_settings(
+            &config.settings,
+            &roots,
+            selected_path,
+            ProjectSettings::load(session_path)?,
+        );
 
-            eprintln!("Created {}", new_path_str)
+        match &args.command {
+            Some(ArgCommand::New {
+                dir_name: new_dir_name,
+                ..
+            }) => {
+                create_new_dir(&settings, &tmux, session_path, new_dir_name)?;
+            }
+            Some(ArgCommand::Stale {
+                kill_session: true, ..
+            }) => {
+                if tmux.has_session(&dir_name) {
+                    tmux.kill_session(&dir_name);
+                    log::info!("Killed session '{dir_name}'");
+                }
+            }
+            Some(ArgCommand::Worktree) => {
+                if let Some(worktree) = pick_worktree(&settings, session_path)? {
+                    let name = name::replace_name(
+                        format!("{dir_name}@{}", worktree.label()),
+                        settings.name_replace.as_deref(),
+                    );
+                    let path = &worktree.path;
+                    let name = start_session(&settings, &tmux, &name, path, path)?;
+                    started.push((name, settings));
+                }
+            }
+            _ if args.popup || settings.popup.unwrap_or(false) => {
+                open_popup(&settings, &tmux, session_path)?;
+            }
+            _ if settings.mode == Some(Mode::Window) && std::env::var("TMUX").is_ok() => {
+                tmux.new_current_window(&dir_name, session_path);
+            }
+            _ => {
+                let name = start_session(&settings, &tmux, &dir_name, session_path, selected_path)?;
+                started.push((name, settings));
+            }
         }
-        None => {
-            new_session(&dir_name, path_str);
+    }
+
+    if let Some((name, settings)) = attach_target(&started, args.attach) {
+        attach_session(settings, &tmux, name)?;
+    }
+
+    Ok(())
+}
+
+/// Adds the `session_options` and `env` of the search path `path` was found under to `settings`
+fn with_root_settings(mut settings: Settings, roots: &[SearchPath], path: &Path) -> Settings {
+    if let Some(root) = source_root(roots, path).map(|i| &roots[i]) {
+        settings
+            .session_options
+            .get_or_insert_default()
+            .extend_from_slice(root.session_options());
+        if let Some(env) = root.env() {
+            settings
+                .env
+                .get_or_insert_default()
+                .extend(env.iter().map(|(k, v)| (k.clone(), v.clone())));
         }
     }
+
+    settings
+}
+
+/// Settings for a session on `path`: the global ones, then its root's, then the project file's,
+/// each overriding the ones before
+fn session_settings(
+    settings: &Settings,
+    roots: &[SearchPath],
+    path: &Path,
+    project: Option<ProjectSettings>,
+) -> Settings {
+    let settings = with_root_settings(settings.clone(), roots, path);
+    match project {
+        Some(project) => project.merge_over(&settings),
+        None => settings,
+    }
+}
+
+/// Runs `pick`, and with `retry` runs it once more if nothing was picked
+fn retry_once<T>(
+    retry: bool,
+    mut pick: impl FnMut() -> Result<Vec<T>, Error>,
+) -> Result<Vec<T>, Error> {
+    let picked = pick()?;
+    if retry && picked.is_empty() {
+        log::debug!("nothing selected, opening the picker again");
+        return pick();
+    }
+
+    Ok(picked)
+}
+
+/// Prints `e`, unless the picker was only canceled, and picks the exit code for it
+fn fail(e: Error) -> std::process::ExitCode {
+    if !matches!(e, Error::Canceled) {
+        eprintln!("{e}");
+    }
+    ExitStatus::from(&e).into()
 }
 
-fn main() {
+fn main() -> std::process::ExitCode {
     let args = Args::parse();
 
-    let (cache_status, config) = Config::try_open().unwrap();
+    let rust_log = std::env::var("RUST_LOG").ok();
+    let verbosity = logging::Verbosity::from_flags(args.quiet, args.verbose);
+    logging::init(logging::level(verbosity, rust_log.as_deref(), None));
+
+    // Runs before loading the config, which may not exist or parse yet
+    if let Some(ArgCommand::Config {
+        command: ConfigCommand::Edit,
+    }) = &args.command
+    {
+        return match edit_config() {
+            Ok(()) => ExitStatus::Success.into(),
+            Err(e) => fail(e),
+        };
+    }
+
+    let cache_mode = if args.no_cache {
+        CacheMode::Skip
+    } else if args.refresh {
+        CacheMode::Refresh
+    } else {
+        CacheMode::Use
+    };
+    let (cache_status, mut config) = match Config::try_open(cache_mode) {
+        Ok(opened) => opened,
+        Err(e) => return fail(e),
+    };
     if cache_status == CacheStatus::Miss {
         config.cache_binary().expect("Failed to save cache file");
     }
 
-    run_command(&config, args.command.as_ref());
+    // Applied after caching, they only hold for this run
+    if let Err(e) = config.settings.apply_env(|name| std::env::var(name).ok()) {
+        return fail(e);
+    }
+    config.settings.picker = resolve_picker(
+        args.picker.as_deref(),
+        std::env::var("TMS_PICKER").ok().as_deref(),
+        config.settings.picker.as_deref(),
+    );
+
+    logging::set_level(logging::level(
+        verbosity,
+        rust_log.as_deref(),
+        config.settings.log_level.as_deref(),
+    ));
+
+    match run_command(&config, &args) {
+        Ok(()) => ExitStatus::Success.into(),
+        Err(e) => fail(e),
+    }
 }
 
 #[cfg(test)]
 mod tests {
-    use crate::config::SearchPath;
-
     use super::*;
+    use config::ShowHidden;
+    use std::{cell::RefCell, ffi::OsStr, os::unix::process::ExitStatusExt, process::ExitStatus};
+
+    /// Records every tmux command's arguments, failing the `failing` subcommands
+    #[derive(Default)]
+    struct FailingRunner {
+        commands: RefCell<Vec<String>>,
+        /// The same commands with their arguments as they were passed
+        args: RefCell<Vec<Vec<std::ffi::OsString>>>,
+        failing: &'static [&'static str],
+    }
+
+    impl FailingRunner {
+        fn record(&self, args: &[&OsStr]) {
+            self.args
+                .borrow_mut()
+                .push(args.iter().map(|a| a.to_os_string()).collect());
+            let args = args.iter().map(|a| a.to_string_lossy()).collect::<Vec<_>>();
+            self.commands.borrow_mut().push(args.join(" "));
+        }
+    }
+
+    impl CommandRunner for &FailingRunner {
+        fn output(&self, _program: &str, args: &[&OsStr]) -> std::io::Result<std::process::Output> {
+            self.record(args);
+            let failed = self.failing.iter().any(|f| args.contains(&OsStr::new(f)));
+            Ok(std::process::Output {
+                status: ExitStatus::from_raw(if failed { 1 << 8 } else { 0 }),
+                stdout: vec![],
+                stderr: b"bad session name\n".to_vec(),
+            })
+        }
+
+        fn spawn(&self, _program: &str, args: &[&OsStr], _wait: bool) -> std::io::Result<()> {
+            self.record(args);
+            Ok(())
+        }
+    }
 
     #[test]
     fn deserialize_test() -> Result<(), Box<dyn std::error::Error>> {
@@ -160,29 +1071,698 @@ mod tests {
             Config {
                 settings: Settings {
                     default_depth: 8,
-                    picker: None
+                    ..Default::default()
                 },
                 paths: vec![
                     SearchPath::Simple("first".into()),
-                    SearchPath::Complex {
-                        path: "second".into(),
-                        depth: None,
-                        show_hidden: None,
-                    },
-                    SearchPath::Complex {
-                        path: "third".into(),
-                        depth: Some(2),
-                        show_hidden: None,
-                    },
-                    SearchPath::Complex {
-                        path: "fourth".into(),
-                        depth: None,
-                        show_hidden: Some(true),
-                    }
+                    SearchPath::complex("second"),
+                    SearchPath::complex("third").with_depth(2),
+                    SearchPath::complex("fourth").with_show_hidden(ShowHidden::All),
                 ]
             }
         );
 
         Ok(())
     }
+
+    #[test]
+    fn session_dir_test() {
+        let dir = tempfile::tempdir().unwrap();
+        let file = dir.path().join("notes.md");
+        std::fs::write(&file, "").unwrap();
+
+        assert_eq!(session_dir(&file), dir.path());
+        assert_eq!(session_dir(dir.path()), dir.path());
+    }
+
+    #[test]
+    fn write_list_test() {
+        let paths = [PathBuf::from("/code/api"), PathBuf::from("/code/web")];
+        let mut out = vec![];
+        write_list(&mut out, &paths, ListFormat::Plain, true).unwrap();
+
+        assert_eq!(String::from_utf8(out).unwrap(), "/code/api\n/code/web\n");
+    }
+
+    #[test]
+    fn write_count_test() {
+        use std::os::unix::ffi::OsStrExt;
+
+        let paths = [
+            PathBuf::from("/code/api"),
+            PathBuf::from("/code/web"),
+            PathBuf::from(std::ffi::OsStr::from_bytes(b"/code/caf\xe9")),
+        ];
+        let mut out = vec![];
+        write_count(&mut out, &paths).unwrap();
+        assert_eq!(out, b"2\n");
+    }
+
+    #[test]
+    fn rofi_entry_test() {
+        assert_eq!(
+            rofi_entry("/code/api", Some("git")),
+            "/code/api\0icon\x1fgit"
+        );
+        assert_eq!(rofi_entry("/code/notes", None), "/code/notes");
+
+        let mut out = vec![];
+        write_list(
+            &mut out,
+            &[PathBuf::from("/code/web")],
+            ListFormat::Rofi,
+            true,
+        )
+        .unwrap();
+        assert_eq!(out, b"/code/web\0icon\x1ffolder\n");
+    }
+
+    #[test]
+    fn new_dir_path_test() {
+        let dir = tempfile::tempdir().unwrap();
+        std::fs::create_dir(dir.path().join("empty")).unwrap();
+        std::fs::create_dir_all(dir.path().join("used/src")).unwrap();
+
+        assert_eq!(
+            new_dir_path(dir.path(), "api").unwrap(),
+            dir.path().join("api")
+        );
+        assert_eq!(
+            new_dir_path(dir.path(), "empty").unwrap(),
+            dir.path().join("empty")
+        );
+        assert!(new_dir_path(dir.path(), "used").is_err());
+        assert!(new_dir_path(dir.path(), "a/b").is_err());
+        assert!(new_dir_path(dir.path(), "..").is_err());
+        assert!(new_dir_path(dir.path(), "").is_err());
+    }
+
+    #[test]
+    fn attach_action_test() {
+        assert_eq!(attach_action(false, None), AttachAction::Attach);
+        assert_eq!(
+            attach_action(false, Some(InsideAction::NewWindowToSession)),
+            AttachAction::Attach
+        );
+        assert_eq!(attach_action(true, None), AttachAction::Switch);
+        assert_eq!(
+            attach_action(true, Some(InsideAction::Switch)),
+            AttachAction::Switch
+        );
+        assert_eq!(
+            attach_action(true, Some(InsideAction::Attach)),
+            AttachAction::Attach
+        );
+        assert_eq!(
+            attach_action(true, Some(InsideAction::NewWindowToSession)),
+            AttachAction::NewWindow
+        );
+    }
+
+    #[test]
+    fn most_recent_test() {
+        let dir = tempfile::tempdir().unwrap();
+        let base =
+            std::time::SystemTime::UNIX_EPOCH + std::time::Duration::from_secs(1_700_000_000);
+
+        let paths = ["old", "newest", "middle", "new"].map(|name| dir.path().join(name));
+        for (path, age) in paths.iter().zip([300, 0, 200, 100]) {
+            std::fs::create_dir(path).unwrap();
+            std::fs::File::open(path)
+                .unwrap()
+                .set_modified(base - std::time::Duration::from_secs(age))
+                .unwrap();
+        }
+
+        assert_eq!(
+            most_recent(paths.to_vec(), 3),
+            [paths[1].clone(), paths[3].clone(), paths[2].clone()]
+        );
+        assert_eq!(most_recent(paths.to_vec(), 10).len(), 4);
+    }
+
+    #[test]
+    fn stale_test() {
+        let dir = tempfile::tempdir().unwrap();
+        let now = std::time::SystemTime::UNIX_EPOCH + std::time::Duration::from_secs(1_700_000_000);
+        let day = std::time::Duration::from_secs(24 * 60 * 60);
+
+        let paths = ["fresh", "month", "year"].map(|name| dir.path().join(name));
+        for (path, days) in paths.iter().zip([1, 31, 365]) {
+            std::fs::create_dir(path).unwrap();
+            std::fs::File::open(path)
+                .unwrap()
+                .set_modified(now - day * days)
+                .unwrap();
+        }
+        let mut with_missing = paths.to_vec();
+        with_missing.push(dir.path().join("missing"));
+
+        assert_eq!(
+            stale(with_missing, now, 30),
+            [paths[1].clone(), paths[2].clone()]
+        );
+        assert_eq!(stale(paths.to_vec(), now, 100), [paths[2].clone()]);
+        assert_eq!(stale(paths.to_vec(), now, 0).len(), 3);
+        assert!(stale(paths.to_vec(), now, 1_000_000_000_000).is_empty());
+        assert!(stale(paths.to_vec(), now, u64::MAX).is_empty());
+    }
+
+    #[test]
+    fn match_cwd_test() {
+        let dirs = ["/code", "/code/api", "/code/api-v2", "/notes"].map(PathBuf::from);
+
+        assert_eq!(match_cwd(&dirs, Path::new("/code/api/src/bin")), Some(1));
+        assert_eq!(match_cwd(&dirs, Path::new("/code/api")), Some(1));
+        assert_eq!(match_cwd(&dirs, Path::new("/code/web")), Some(0));
+        assert_eq!(match_cwd(&dirs, Path::new("/home")), None);
+    }
+
+    #[test]
+    fn resolve_picker_test() {
+        assert_eq!(
+            resolve_picker(Some("sk --ansi"), Some("fzf"), Some("fzf-tmux")).as_deref(),
+            Some("sk --ansi")
+        );
+        assert_eq!(
+            resolve_picker(None, Some("fzf"), Some("fzf-tmux")).as_deref(),
+            Some("fzf")
+        );
+        assert_eq!(
+            resolve_picker(None, None, Some("fzf-tmux")).as_deref(),
+            Some("fzf-tmux")
+        );
+        assert_eq!(resolve_picker(None, None, None), None);
+    }
+
+    #[test]
+    fn name_for_path_test() {
+        let dir = tempfile::tempdir().unwrap();
+        let project = dir.path().join("web");
+        std::fs::create_dir(&project).unwrap();
+        std::fs::write(project.join("package.json"), r#"{ "name": "shop.front" }"#).unwrap();
+        std::fs::write(project.join("notes.md"), "").unwrap();
+
+        let settings = Settings {
+            name_from: Some(config::NameSource::Project),
+            ..Default::default()
+        };
+
+        assert_eq!(name_for_path(&settings, None, &project), "shop_front");
+        assert_eq!(
+            name_for_path(&settings, None, &project.join("notes.md")),
+            "shop_front"
+        );
+        assert_eq!(
+            name_for_path(&settings, None, &dir.path().join("my.api")),
+            "my_api"
+        );
+        assert_eq!(name_for_path(&Settings::default(), None, &project), "web");
+        assert_eq!(
+            name_for_path(&Settings::default(), None, Path::new("/")),
+            "session"
+        );
+
+        let prefixed = Settings {
+            prefix_with_profile: Some(true),
+            ..Default::default()
+        };
+        assert_eq!(name_for_path(&prefixed, Some("work"), &project), "work/web");
+        assert_eq!(name_for_path(&prefixed, None, &project), "web");
+    }
+
+    #[test]
+    fn exclude_dir_test() {
+        let dirs = ["/code", "/code/api", "/code/web"]
+            .map(PathBuf::from)
+            .to_vec();
+
+        assert_eq!(
+            exclude_dir(dirs.clone(), Path::new("/code/api/")),
+            [PathBuf::from("/code"), PathBuf::from("/code/web")]
+        );
+        assert_eq!(exclude_dir(dirs.clone(), Path::new("/notes")), dirs);
+    }
+
+    #[test]
+    fn renamed_test() {
+        let session = |name: &str, path: &str| tmux::Session {
+            name: name.into(),
+            activity: 0,
+            path: path.into(),
+        };
+        let settings = Settings::default();
+
+        assert_eq!(
+            renamed(&settings, None, &session("3", "/code/my.api")).as_deref(),
+            Some("my_api")
+        );
+        assert_eq!(
+            renamed(&settings, None, &session("my_api", "/code/my.api")),
+            None
+        );
+    }
+
+    #[test]
+    fn session_shell_test() {
+        assert_eq!(
+            session_shell(Some("fish"), false, Some("/bin/zsh")).as_deref(),
+            Some("fish")
+        );
+        assert_eq!(
+            session_shell(Some("fish"), true, Some("/bin/zsh")).as_deref(),
+            Some("fish -l")
+        );
+        assert_eq!(
+            session_shell(None, true, Some("/bin/zsh")).as_deref(),
+            Some("/bin/zsh -l")
+        );
+        assert_eq!(session_shell(None, false, Some("/bin/zsh")), None);
+        assert_eq!(session_shell(None, true, None), None);
+    }
+
+    #[test]
+    fn direnv_command_test() {
+        assert_eq!(
+            direnv_command("/code/api", "zsh -l"),
+            "direnv exec /code/api zsh -l"
+        );
+        assert_eq!(
+            direnv_command("/code/my api", "zsh"),
+            "direnv exec '/code/my api' zsh"
+        );
+        assert_eq!(
+            direnv_command("/code/it's", "fish"),
+            r"direnv exec '/code/it'\''s' fish"
+        );
+    }
+
+    #[test]
+    fn resolve_selected_test() {
+        let dir = tempfile::tempdir().unwrap();
+        let real = dir.path().join("projects/api");
+        let link = dir.path().join("current");
+        std::fs::create_dir_all(&real).unwrap();
+        std::os::unix::fs::symlink(&real, &link).unwrap();
+
+        let settings = Settings {
+            name_from_realpath: Some(true),
+            ..Default::default()
+        };
+        let resolved = resolve_selected(&settings, link.clone());
+        assert_eq!(resolved, real.canonicalize().unwrap());
+        assert_eq!(name_for_path(&settings, None, &resolved), "api");
+
+        let resolved = resolve_selected(&Settings::default(), link.clone());
+        assert_eq!(
+            name_for_path(&Settings::default(), None, &resolved),
+            "current"
+        );
+    }
+
+    #[test]
+    fn taken_elsewhere_test() {
+        let sessions = [
+            tmux::Session {
+                name: "session".into(),
+                activity: 0,
+                path: "/".into(),
+            },
+            tmux::Session {
+                name: "session-2".into(),
+                activity: 0,
+                path: "/mnt/usb".into(),
+            },
+        ];
+        let name = |path: &str| {
+            name::non_empty_name(String::new(), |n| {
+                taken_elsewhere(&sessions, n, Path::new(path))
+            })
+        };
+
+        assert_eq!(name("/"), "session");
+        assert_eq!(name("/mnt/usb"), "session-2");
+        assert_eq!(name("/mnt/disk"), "session-3");
+    }
+
+    #[test]
+    fn unique_session_match_test() {
+        let sessions = ["api", "web-api", "notes"].map(|name| tmux::Session {
+            name: name.into(),
+            activity: 0,
+            path: format!("/code/{name}"),
+        });
+
+        assert_eq!(
+            unique_session_match(&sessions, "NOT").as_deref(),
+            Some("notes")
+        );
+        assert_eq!(
+            unique_session_match(&sessions, "web").as_deref(),
+            Some("web-api")
+        );
+        assert_eq!(unique_session_match(&sessions, "api"), None);
+        assert_eq!(unique_session_match(&sessions, "docs"), None);
+    }
+
+    #[test]
+    fn write_selection_test() {
+        let dir = tempfile::tempdir().unwrap();
+        let output = dir.path().join("selection");
+        std::fs::write(&output, "/old/selection\n").unwrap();
+
+        write_selection(&output, &[PathBuf::from("/code/api")]).unwrap();
+
+        assert_eq!(std::fs::read_to_string(&output).unwrap(), "/code/api\n");
+        assert!(!dir.path().join("selection.tmp").exists());
+    }
+
+    #[test]
+    fn check_selected_test() {
+        let dir = tempfile::tempdir().unwrap();
+        let file = dir.path().join("notes.md");
+        std::fs::write(&file, "").unwrap();
+
+        assert!(check_selected(dir.path()).is_ok());
+        assert!(check_selected(&file).is_ok());
+        assert!(matches!(
+            check_selected(&dir.path().join("removed")),
+            Err(Error::MissingSelection(path)) if path.ends_with("removed")
+        ));
+    }
+
+    #[test]
+    fn most_recent_tie_test() {
+        let dir = tempfile::tempdir().unwrap();
+        let modified =
+            std::time::SystemTime::UNIX_EPOCH + std::time::Duration::from_secs(1_700_000_000);
+
+        let paths = ["b", "a", "c"].map(|name| dir.path().join(name));
+        for path in &paths {
+            std::fs::create_dir(path).unwrap();
+            std::fs::File::open(path)
+                .unwrap()
+                .set_modified(modified)
+                .unwrap();
+        }
+
+        let mut reversed = paths.to_vec();
+        reversed.reverse();
+        let expected = ["a", "b", "c"].map(|name| dir.path().join(name));
+
+        assert_eq!(most_recent(paths.to_vec(), 3), expected);
+        assert_eq!(most_recent(reversed, 3), expected);
+    }
+
+    #[test]
+    fn relative_selection_test() {
+        let cwd = std::env::current_dir().unwrap();
+        let resolved = resolve_selected(&Settings::default(), PathBuf::from("code/api"));
+
+        assert!(resolved.is_absolute());
+        assert_eq!(resolved, cwd.join("code/api"));
+    }
+
+    #[test]
+    fn attach_target_test() {
+        let started = ["api", "web", "docs"];
+
+        assert_eq!(attach_target(&started, AttachTarget::First), Some(&"api"));
+        assert_eq!(attach_target(&started, AttachTarget::Last), Some(&"docs"));
+        assert_eq!(attach_target(&started, AttachTarget::None), None);
+        assert_eq!(attach_target::<&str>(&[], AttachTarget::Last), None);
+    }
+
+    #[test]
+    fn effective_config_test() {
+        let yml = r#"
+            settings:
+                default_depth: 2
+                picker: fzf
+                name_replace:
+                  - { from: " ", to: "_" }
+            paths:
+                - /code
+                - { path: /srv, depth: 1, show_hidden: top, weight: 3 }
+        "#;
+        let config: Config = serde_yml::from_str(yml).unwrap();
+
+        let shown = effective_config(&config).unwrap();
+        let reparsed: Config = serde_yml::from_str(&shown).unwrap();
+        assert_eq!(reparsed.settings, config.settings);
+        assert_eq!(reparsed.paths, discovery::roots(&config));
+    }
+
+    #[test]
+    fn failed_session_test() {
+        let runner = FailingRunner {
+            failing: &["has-session", "new-session"],
+            ..Default::default()
+        };
+        let tmux = Tmux::with_runner(&Settings::default(), &runner);
+        let result = new_session(
+            &Settings::default(),
+            &tmux,
+            "api",
+            Path::new("/code/api"),
+            Path::new("/code/api"),
+        );
+
+        assert!(matches!(result, Err(Error::NewSession(e)) if e == "'api': bad session name"));
+        let commands = runner.commands.into_inner();
+        assert!(commands.last().unwrap().starts_with("new-session"));
+        assert!(
+            !commands
+                .iter()
+                .any(|c| c.contains("attach") || c.contains("switch"))
+        );
+    }
+
+    #[test]
+    fn session_options_test() {
+        let runner = FailingRunner {
+            failing: &["has-session"],
+            ..Default::default()
+        };
+        let settings = Settings {
+            session_options: Some(vec![
+                "destroy-unattached off".into(),
+                "status-style bg=blue".into(),
+            ]),
+            ..Default::default()
+        };
+        let tmux = Tmux::with_runner(&settings, &runner);
+        start_session(
+            &settings,
+            &tmux,
+            "api",
+            Path::new("/code/api"),
+            Path::new("/code/api"),
+        )
+        .unwrap();
+
+        let commands = runner.commands.into_inner();
+        let options = commands
+            .iter()
+            .skip_while(|c| !c.starts_with("new-session"))
+            .skip(1)
+            .collect::<Vec<_>>();
+        assert_eq!(
+            options,
+            [
+                "set-option -t api destroy-unattached off",
+                "set-option -t api status-style bg=blue",
+            ]
+        );
+    }
+
+    #[test]
+    fn non_utf8_session_test() {
+        use std::os::unix::ffi::OsStrExt;
+
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join(OsStr::from_bytes(b"caf\xe9"));
+        std::fs::create_dir(&path).unwrap();
+        let settings = Settings {
+            picker: Some("head -n1".into()),
+            lossy_paths: Some(true),
+            on_create: Some("ls {path}".into()),
+            ..Default::default()
+        };
+
+        let selected = run_finder(
+            &settings,
+            &PickerOptions::default(),
+            std::slice::from_ref(&path),
+            &[],
+        )
+        .unwrap()
+        .remove(0);
+        check_selected(&selected).unwrap();
+        let name = name::session_name(&settings, &selected, session_dir(&selected));
+        assert_eq!(name, "caf\u{FFFD}");
+
+        let runner = FailingRunner {
+            failing: &["has-session"],
+            ..Default::default()
+        };
+        let tmux = Tmux::with_runner(&settings, &runner);
+        start_session(&settings, &tmux, &name, session_dir(&selected), &selected).unwrap();
+
+        let args = runner.args.into_inner();
+        let new_session = args.iter().find(|a| a[0] == "new-session").unwrap();
+        assert_eq!(
+            new_session[..5],
+            [
+                OsStr::new("new-session"),
+                OsStr::new("-c"),
+                path.as_os_str(),
+                OsStr::new("-s"),
+                OsStr::new(&name)
+            ]
+        );
+        let send_keys = args.iter().find(|a| a[0] == "send-keys").unwrap();
+        assert_eq!(send_keys[3], fill_path("ls {path}", &path));
+        assert_eq!(
+            fill_path("ls {path}", &path).as_bytes(),
+            [b"ls ", path.as_os_str().as_bytes()].concat()
+        );
+    }
+
+    #[test]
+    fn on_create_delay_test() {
+        let runner = FailingRunner {
+            failing: &["has-session"],
+            ..Default::default()
+        };
+        let settings = Settings {
+            on_create: Some("nvim".into()),
+            on_create_delay_ms: Some(50),
+            ..Default::default()
+        };
+        let tmux = Tmux::with_runner(&settings, &runner);
+
+        let start = std::time::Instant::now();
+        start_session(
+            &settings,
+            &tmux,
+            "api",
+            Path::new("/code/api"),
+            Path::new("/code/api"),
+        )
+        .unwrap();
+        assert!(start.elapsed() >= Duration::from_millis(50));
+        assert!(
+            runner
+                .commands
+                .into_inner()
+                .last()
+                .unwrap()
+                .starts_with("send-keys")
+        );
+    }
+
+    #[test]
+    fn moved_session_test() {
+        let dir = tempfile::tempdir().unwrap();
+        let file = dir.path().join("notes.md");
+        std::fs::write(&file, "").unwrap();
+
+        assert_eq!(moved_session(dir.path()), None);
+        assert_eq!(moved_session(&file), Some("not a directory"));
+        assert_eq!(moved_session(&dir.path().join("renamed")), Some("missing"));
+    }
+
+    #[test]
+    fn retry_once_test() {
+        let mut results = vec![vec![], vec![PathBuf::from("/code/api")]].into_iter();
+        let mut calls = 0;
+        let picked = retry_once(true, || {
+            calls += 1;
+            Ok(results.next().unwrap_or_default())
+        });
+        assert_eq!(picked.unwrap(), [PathBuf::from("/code/api")]);
+        assert_eq!(calls, 2);
+
+        let mut calls = 0;
+        let picked = retry_once(true, || {
+            calls += 1;
+            Ok(Vec::<PathBuf>::new())
+        });
+        assert!(picked.unwrap().is_empty());
+        assert_eq!(calls, 2);
+
+        let mut calls = 0;
+        let picked = retry_once(false, || {
+            calls += 1;
+            Ok(Vec::<PathBuf>::new())
+        });
+        assert!(picked.unwrap().is_empty());
+        assert_eq!(calls, 1);
+    }
+
+    #[test]
+    fn root_env_test() {
+        let roots: Vec<SearchPath> = serde_yml::from_str(
+            "[{ path: /code/work, env: { AWS_PROFILE: '${TMS_TEST_UNSET:-work}', EDITOR: nvim } }, /code]",
+        )
+        .unwrap();
+        let roots = roots
+            .iter()
+            .map(|r| r.expand().unwrap())
+            .collect::<Vec<_>>();
+        let global = Settings {
+            env: Some([("EDITOR".into(), "vim".into())].into()),
+            ..Default::default()
+        };
+
+        let settings = with_root_settings(global.clone(), &roots, Path::new("/code/work/api"));
+        let runner = FailingRunner {
+            failing: &["has-session"],
+            ..Default::default()
+        };
+        let tmux = Tmux::with_runner(&settings, &runner);
+        start_session(
+            &settings,
+            &tmux,
+            "api",
+            Path::new("/code/work/api"),
+            Path::new("/code/work/api"),
+        )
+        .unwrap();
+
+        let commands = runner.commands.into_inner();
+        let new_session = commands
+            .iter()
+            .find(|c| c.starts_with("new-session"))
+            .unwrap();
+        assert!(new_session.ends_with("-e AWS_PROFILE=work -e EDITOR=nvim"));
+
+        let settings = with_root_settings(global, &roots, Path::new("/code/notes"));
+        assert_eq!(settings.env, Some([("EDITOR".into(), "vim".into())].into()));
+    }
+
+    #[test]
+    fn session_settings_test() {
+        let roots: Vec<SearchPath> =
+            serde_yml::from_str("[{ path: /code/work, session_options: [status off] }]").unwrap();
+        let global = Settings {
+            shell: Some("bash".into()),
+            layout: Some(vec![]),
+            ..Default::default()
+        };
+        let project = ProjectSettings {
+            shell: Some("zsh".into()),
+            ..Default::default()
+        };
+
+        let settings =
+            session_settings(&global, &roots, Path::new("/code/work/api"), Some(project));
+        assert_eq!(settings.shell.as_deref(), Some("zsh"));
+        assert_eq!(settings.layout, Some(vec![]));
+        assert_eq!(settings.session_options, Some(vec!["status off".into()]));
+
+        let settings = session_settings(&global, &roots, Path::new("/code/work/api"), None);
+        assert_eq!(settings.shell.as_deref(), Some("bash"));
+    }
 }
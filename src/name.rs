@@ -0,0 +1,258 @@
+use crate::config::{NameReplace, NameSource, Settings};
+use std::path::Path;
+
+/// The last component of `dir`, empty for `/`. tmux names are strings, so one that isn't
+/// UTF-8 is converted lossily
+fn get_dir_name(dir: &Path) -> String {
+    dir.file_name()
+        .map(|s| s.to_string_lossy().into_owned())
+        .unwrap_or_default()
+}
+
+/// The file stem of `selected` when it's a file, otherwise its full name
+fn name_base(selected: &Path, strip_extension: bool) -> String {
+    match selected.file_stem() {
+        Some(stem) if strip_extension && selected.is_file() => stem.to_string_lossy().into_owned(),
+        _ => get_dir_name(selected),
+    }
+}
+
+fn parse_package_json(s: &str) -> Option<String> {
+    let json = serde_json::from_str::<serde_json::Value>(s).ok()?;
+    json.get("name")?.as_str().map(str::to_string)
+}
+
+/// Reads `<table>.name` from a toml document
+fn parse_toml_name(s: &str, table: &str) -> Option<String> {
+    let toml = s.parse::<toml::Table>().ok()?;
+    toml.get(table)?.get("name")?.as_str().map(str::to_string)
+}
+
+fn parse_cargo_toml(s: &str) -> Option<String> {
+    parse_toml_name(s, "package")
+}
+
+fn parse_pyproject_toml(s: &str) -> Option<String> {
+    parse_toml_name(s, "project")
+}
+
+type ProjectFileParser = fn(&str) -> Option<String>;
+
+/// Name from the first known project file in `dir`
+fn project_name(dir: &Path) -> Option<String> {
+    let parsers: [(&str, ProjectFileParser); 3] = [
+        ("package.json", parse_package_json),
+        ("Cargo.toml", parse_cargo_toml),
+        ("pyproject.toml", parse_pyproject_toml),
+    ];
+
+    parsers.into_iter().find_map(|(file, parse)| {
+        let contents = std::fs::read_to_string(dir.join(file)).ok()?;
+        parse(&contents)
+    })
+}
+
+/// Session name for `selected`, where `dir` is the directory the session starts in
+pub fn session_name(settings: &Settings, selected: &Path, dir: &Path) -> String {
+    let name = match settings.name_from.unwrap_or_default() {
+        NameSource::Dir => None,
+        NameSource::Project => project_name(dir),
+    }
+    .unwrap_or_else(|| name_base(selected, settings.strip_extension_for_name.unwrap_or(false)));
+
+    replace_name(name, settings.name_replace.as_deref())
+}
+
+/// Applies `rules`, or the default `.` → `_` when unset. `:` separates targets in tmux,
+/// so it's always replaced
+pub fn replace_name(name: String, rules: Option<&[NameReplace]>) -> String {
+    let name = match rules {
+        Some(rules) => rules
+            .iter()
+            .filter(|r| !r.from.is_empty())
+            .fold(name, |name, r| name.replace(&r.from, &r.to)),
+        None => name.replace('.', "_"),
+    };
+
+    name.replace(':', "_")
+}
+
+/// Lowercases `name` and turns each run of punctuation or spaces into one `_`
+fn normalize(name: &str) -> String {
+    name.split(|c: char| !c.is_alphanumeric())
+        .filter(|part| !part.is_empty())
+        .map(str::to_lowercase)
+        .collect::<Vec<_>>()
+        .join("_")
+}
+
+/// The first of `sessions` that's the same as `name` once both are normalized
+pub fn matching_session<'a>(
+    sessions: impl IntoIterator<Item = &'a str>,
+    name: &str,
+) -> Option<&'a str> {
+    let name = normalize(name);
+    sessions.into_iter().find(|s| normalize(s) == name)
+}
+
+/// `name` prefixed with `<profile>/` when a profile is active and `prefix_with_profile` is set
+pub fn with_profile(settings: &Settings, profile: Option<&str>, name: String) -> String {
+    match profile {
+        Some(profile) if settings.prefix_with_profile.unwrap_or(false) => {
+            format!("{profile}/{name}")
+        }
+        _ => name,
+    }
+}
+
+/// `base`, or the first of `base-2`, `base-3`, ... not `taken`
+pub fn next_free_name(base: &str, taken: impl Fn(&str) -> bool) -> String {
+    std::iter::once(base.to_string())
+        .chain((2..).map(|i| format!("{base}-{i}")))
+        .find(|n| !taken(n))
+        .expect("Ran out of session names")
+}
+
+/// `name`, or the first free of `session`, `session-2`, ... for an empty name
+pub fn non_empty_name(name: String, taken: impl Fn(&str) -> bool) -> String {
+    if !name.is_empty() {
+        return name;
+    }
+
+    let fallback = next_free_name("session", taken);
+
+    log::warn!("session name is empty, using '{fallback}'");
+    fallback
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn package_json_test() {
+        assert_eq!(
+            parse_package_json(r#"{ "name": "web-app", "version": "1.0.0" }"#),
+            Some("web-app".into())
+        );
+        assert_eq!(parse_package_json(r#"{ "version": "1.0.0" }"#), None);
+        assert_eq!(parse_package_json("not json"), None);
+    }
+
+    #[test]
+    fn cargo_toml_test() {
+        let toml = r#"
+            [package]
+            name = "tmux-sessionizer"
+            version = "0.4.0"
+        "#;
+
+        assert_eq!(parse_cargo_toml(toml), Some("tmux-sessionizer".into()));
+        assert_eq!(parse_cargo_toml("[workspace]\nmembers = []"), None);
+    }
+
+    #[test]
+    fn pyproject_toml_test() {
+        let toml = r#"
+            [project]
+            name = "data-tools"
+        "#;
+
+        assert_eq!(parse_pyproject_toml(toml), Some("data-tools".into()));
+        assert_eq!(parse_pyproject_toml("[tool.black]"), None);
+    }
+
+    #[test]
+    fn empty_name_test() {
+        let name = session_name(&Settings::default(), Path::new("/"), Path::new("/"));
+        assert_eq!(name, "");
+
+        assert_eq!(non_empty_name(name.clone(), |_| false), "session");
+        assert_eq!(
+            non_empty_name(name, |n| n == "session" || n == "session-2"),
+            "session-3"
+        );
+        assert_eq!(non_empty_name("api".into(), |_| true), "api");
+    }
+
+    #[test]
+    fn matching_session_test() {
+        let settings = Settings::default();
+        let sessions = ["notes", "my_api"];
+
+        for dir in ["/code/my.api", "/code/My API", "/code/my-api"] {
+            let name = session_name(&settings, Path::new(dir), Path::new(dir));
+            assert_eq!(matching_session(sessions, &name), Some("my_api"), "{dir}");
+        }
+        assert_eq!(matching_session(sessions, "myapi"), None);
+    }
+
+    #[test]
+    fn name_replace_test() {
+        let rule = |from: &str, to: &str| NameReplace {
+            from: from.into(),
+            to: to.into(),
+        };
+
+        assert_eq!(replace_name("my.api".into(), None), "my_api");
+        assert_eq!(replace_name("my.api".into(), Some(&[])), "my.api");
+        assert_eq!(replace_name("my.api:v2".into(), Some(&[])), "my.api_v2");
+        assert_eq!(
+            replace_name("my.api v2".into(), Some(&[rule(" ", "-"), rule(".", "")])),
+            "myapi-v2"
+        );
+    }
+
+    #[test]
+    fn strip_extension_test() {
+        let dir = tempfile::tempdir().unwrap();
+        let notes = dir.path().join("notes.md");
+        std::fs::write(&notes, "").unwrap();
+        let settings = Settings {
+            strip_extension_for_name: Some(true),
+            ..Default::default()
+        };
+
+        assert_eq!(session_name(&settings, &notes, dir.path()), "notes");
+        assert_eq!(
+            session_name(&Settings::default(), &notes, dir.path()),
+            "notes_md"
+        );
+
+        let project = dir.path().join("my.api");
+        std::fs::create_dir(&project).unwrap();
+        assert_eq!(session_name(&settings, &project, &project), "my_api");
+    }
+
+    #[test]
+    fn next_free_name_test() {
+        let taken = |names: &'static [&str]| move |n: &str| names.contains(&n);
+
+        assert_eq!(next_free_name("api", taken(&[])), "api");
+        assert_eq!(next_free_name("api", taken(&["api"])), "api-2");
+        assert_eq!(
+            next_free_name("api", taken(&["api", "api-2", "api-3"])),
+            "api-4"
+        );
+        assert_eq!(next_free_name("api", taken(&["api", "api-3"])), "api-2");
+        assert_eq!(next_free_name("api", taken(&["api-2", "web"])), "api");
+    }
+
+    #[test]
+    fn with_profile_test() {
+        let settings = Settings {
+            prefix_with_profile: Some(true),
+            ..Default::default()
+        };
+
+        assert_eq!(
+            with_profile(&settings, Some("work"), "api".into()),
+            "work/api"
+        );
+        assert_eq!(with_profile(&settings, None, "api".into()), "api");
+        assert_eq!(
+            with_profile(&Settings::default(), Some("work"), "api".into()),
+            "api"
+        );
+    }
+}
@@ -0,0 +1,749 @@
+use crate::{
+    config::{SearchPath, Settings, source_root},
+    error::Error,
+    git,
+};
+use std::{
+    collections::HashMap,
+    io::{ErrorKind, Write},
+    path::{Path, PathBuf},
+    process::{Command, Stdio},
+};
+
+const DEFAULT_PICKER: &str = "fzf-tmux -p 50%";
+
+// Nerd Font glyphs
+const GIT_ICON: &str = "\u{e725}";
+const FOLDER_ICON: &str = "\u{f07b}";
+
+/// Picker options taken from the command line for a single run
+#[derive(Debug, Default, Clone, Copy)]
+pub struct PickerOptions<'a> {
+    pub query: Option<&'a str>,
+    /// Lines start with a tab separated preview command (fzf pickers only)
+    pub preview: bool,
+    /// Index of the entry to start on (fzf pickers only), `run_finder` takes an index of its paths
+    pub select: Option<usize>,
+    /// Allow selecting several entries (fzf pickers only)
+    pub multi: bool,
+    /// Header line shown above the entries (fzf pickers only)
+    pub header: Option<&'a str>,
+    /// Active profile, fills `{profile}` in `settings.picker_header`
+    pub profile: Option<&'a str>,
+}
+
+/// Whether the picker understands fzf's flags (`fzf` and `fzf-tmux`)
+fn is_fzf_family(cmd: &str) -> bool {
+    Path::new(cmd)
+        .file_name()
+        .and_then(|n| n.to_str())
+        .map(|n| n == "fzf" || n == "fzf-tmux")
+        .unwrap_or(false)
+}
+
+/// Splits a picker command into words the way a shell would, so quoted paths can hold spaces
+fn split_command(picker: &str) -> Vec<String> {
+    shell_words::split(picker).unwrap_or_else(|e| {
+        log::warn!("can't parse picker '{picker}': {e}, splitting it on spaces");
+        picker.split_whitespace().map(str::to_string).collect()
+    })
+}
+
+fn picker_command<'a>(
+    words: &'a [String],
+    settings: &'a Settings,
+    options: &PickerOptions<'a>,
+) -> (&'a str, Vec<&'a str>) {
+    let (cmd, mut args) = match words.split_first() {
+        Some((cmd, args)) => (cmd.as_str(), args.iter().map(String::as_str).collect()),
+        None => ("", vec![]),
+    };
+
+    args.extend(
+        settings
+            .picker_extra_args
+            .iter()
+            .flatten()
+            .map(String::as_str),
+    );
+
+    if is_fzf_family(cmd)
+        && let Some(query) = options.query
+    {
+        args.extend(["--query", query]);
+    }
+
+    if is_fzf_family(cmd) && options.multi {
+        args.push("--multi");
+    }
+
+    if is_fzf_family(cmd) && settings.picker_exact.unwrap_or(false) {
+        args.push("--exact");
+    }
+
+    if is_fzf_family(cmd)
+        && let Some(header) = options.header
+    {
+        args.extend(["--header", header]);
+    }
+
+    if settings.picker_null_delimited.unwrap_or(false) {
+        args.extend(["--read0", "--print0"]);
+    }
+
+    if is_fzf_family(cmd) && options.preview {
+        args.extend([
+            "--delimiter",
+            "\t",
+            "--with-nth",
+            "2..",
+            "--preview",
+            "eval {1}",
+        ]);
+    }
+
+    (cmd, args)
+}
+
+fn with_icon(path: &str, is_repo: bool) -> String {
+    let icon = if is_repo { GIT_ICON } else { FOLDER_ICON };
+    format!("{icon} {path}")
+}
+
+/// The line shown in the picker for `path`, `branches` remembers the branch of each repo
+/// already read
+fn display_line(
+    settings: &Settings,
+    path: &str,
+    branches: &mut HashMap<PathBuf, Option<String>>,
+) -> String {
+    let line = if settings.display_icons.unwrap_or(false) {
+        with_icon(path, Path::new(path).join(".git").exists())
+    } else {
+        path.to_string()
+    };
+
+    let branch = settings.show_git_branch.unwrap_or(false).then(|| {
+        branches
+            .entry(PathBuf::from(path))
+            .or_insert_with(|| git::current_branch(Path::new(path)))
+            .clone()
+    });
+    match branch.flatten() {
+        Some(branch) => format!("{line} ({branch})"),
+        None => line,
+    }
+}
+
+/// The directory directly below `root` that `path` is in, `None` for `root` itself
+fn group_prefix<'a>(root: &Path, path: &'a Path) -> Option<&'a str> {
+    path.strip_prefix(root)
+        .ok()?
+        .components()
+        .next()?
+        .as_os_str()
+        .to_str()
+}
+
+/// `template` with `{count}` and `{profile}` filled in
+fn header_text(template: &str, count: usize, profile: Option<&str>) -> String {
+    template
+        .replace("{count}", &count.to_string())
+        .replace("{profile}", profile.unwrap_or_default())
+}
+
+/// `preview` with `{path}` replaced by the single quoted `path`
+fn preview_command(preview: &str, path: &str) -> String {
+    let quoted = format!("'{}'", path.replace('\'', r"'\''"));
+    preview.replace("{path}", &quoted)
+}
+
+/// Separates picker entries, NUL keeps paths containing newlines intact
+fn delimiter(settings: &Settings) -> char {
+    if settings.picker_null_delimited.unwrap_or(false) {
+        '\0'
+    } else {
+        '\n'
+    }
+}
+
+fn join_entries(lines: impl IntoIterator<Item = impl AsRef<str>>, delimiter: char) -> String {
+    let mut input = String::new();
+    for line in lines {
+        input.push_str(line.as_ref());
+        input.push(delimiter);
+    }
+
+    input
+}
+
+/// Strips the delimiter the picker ends its output with
+fn strip_delimiter(output: &str, delimiter: char) -> &str {
+    output.strip_suffix(delimiter).unwrap_or(output)
+}
+
+/// The default picker for `settings.picker_tmux_mode`
+fn default_picker(tmux_mode: Option<&str>) -> &'static str {
+    match tmux_mode {
+        None | Some("popup") => DEFAULT_PICKER,
+        Some("split-down") => "fzf-tmux -d 40%",
+        Some("split-right") => "fzf-tmux -r 40%",
+        Some(mode) => {
+            log::warn!("unknown picker_tmux_mode '{mode}', using a popup");
+            DEFAULT_PICKER
+        }
+    }
+}
+
+fn picker(settings: &Settings) -> &str {
+    settings
+        .picker
+        .as_deref()
+        .unwrap_or_else(|| default_picker(settings.picker_tmux_mode.as_deref()))
+}
+
+/// `fzf-tmux` needs tmux, outside of it the same picker as plain `fzf` without the
+/// popup and split options
+fn without_fzf_tmux(picker: &str) -> Option<String> {
+    fn is_size(arg: &str) -> bool {
+        !arg.is_empty()
+            && arg
+                .chars()
+                .all(|c| c.is_ascii_digit() || c == '%' || c == ',')
+    }
+
+    let words = split_command(picker);
+    let mut words = words.iter().map(String::as_str).peekable();
+    let cmd = words.next()?;
+    if Path::new(cmd).file_name()? != "fzf-tmux" {
+        return None;
+    }
+
+    // The fzf installed next to fzf-tmux, which may not be on PATH
+    let path = Path::new(cmd).with_file_name("fzf");
+    let mut fzf = vec![path.to_str()?];
+    while let Some(word) = words.next() {
+        match word {
+            // Optional size, e.g. `-p 80%,50%`
+            "-p" | "-d" | "-u" | "-l" | "-r" => {
+                words.next_if(|w| is_size(w));
+            }
+            // Required value
+            "-w" | "-h" | "-x" | "-y" => {
+                words.next();
+            }
+            "--" => {}
+            w if w.split_at_checked(2).is_some_and(|(flag, size)| {
+                matches!(flag, "-p" | "-d" | "-u" | "-l" | "-r") && is_size(size)
+            }) => {}
+            w => fzf.push(w),
+        }
+    }
+
+    // Only quote what `split_command` would split differently
+    let words = fzf.into_iter().map(|w| {
+        if w.contains(char::is_whitespace) || w.contains(['\'', '"', '\\']) {
+            shell_words::quote(w)
+        } else {
+            w.into()
+        }
+    });
+    Some(words.collect::<Vec<_>>().join(" "))
+}
+
+/// Runs the picker over `lines`, returning the selected line
+pub fn run_picker(
+    settings: &Settings,
+    options: &PickerOptions,
+    lines: impl IntoIterator<Item = impl AsRef<str>>,
+) -> Result<Option<String>, Error> {
+    let fallback = std::env::var("TMUX")
+        .is_err()
+        .then(|| without_fzf_tmux(picker(settings)))
+        .flatten();
+    if let Some(fallback) = &fallback {
+        log::warn!("fzf-tmux needs tmux, running '{fallback}' instead");
+    }
+    let picker = fallback.as_deref().unwrap_or(picker(settings));
+
+    let delimiter = delimiter(settings);
+    let input = join_entries(lines, delimiter);
+
+    let words = split_command(picker);
+    let (cmd, mut args) = picker_command(&words, settings, options);
+    let bind = options
+        .select
+        .filter(|_| is_fzf_family(cmd))
+        .map(|i| format!("load:pos({})", i + 1));
+    if let Some(bind) = &bind {
+        args.extend(["--bind", bind]);
+    }
+
+    let mut proc = Command::new(cmd)
+        .args(args)
+        .stdin(Stdio::piped())
+        .stdout(Stdio::piped())
+        .spawn()
+        .map_err(|e| match e.kind() {
+            ErrorKind::NotFound => Error::PickerNotFound(cmd.to_string()),
+            _ => Error::PickerSpawn(format!("'{picker}': {e}")),
+        })?;
+
+    // Write from another thread, a picker that prints while reading blocks on a full
+    // stdout pipe until we start reading it
+    let mut stdin = proc
+        .stdin
+        .take()
+        .ok_or_else(|| Error::PickerSpawn(format!("'{picker}': stdin isn't piped")))?;
+    let writer = std::thread::spawn(move || match stdin.write_all(input.as_bytes()) {
+        // The picker exited without reading everything
+        Err(e) if e.kind() == ErrorKind::BrokenPipe => {}
+        res => res.expect("Failed to write to stdin"),
+    });
+
+    let res = proc
+        .wait_with_output()
+        .map_err(|e| Error::PickerSpawn(format!("'{picker}': {e}")))?;
+    writer.join().expect("Failed to write to stdin");
+
+    if res.status.success() {
+        let s = String::from_utf8(res.stdout).expect("Picker output is not UTF-8");
+        Ok(Some(strip_delimiter(&s, delimiter).to_string()))
+    } else {
+        Ok(None)
+    }
+}
+
+/// Runs the picker over `paths`, `roots` are the search paths they were found under.
+/// Returns the selected paths, none if the picker was canceled
+pub fn run_finder(
+    settings: &Settings,
+    options: &PickerOptions,
+    paths: &[PathBuf],
+    roots: &[SearchPath],
+) -> Result<Vec<PathBuf>, Error> {
+    let root = |p: &Path| source_root(roots, p).map(|i| &roots[i]);
+    let preview = |p: &Path| root(p).and_then(SearchPath::preview);
+
+    let words = split_command(picker(settings));
+    let cmd = words.first().map(String::as_str).unwrap_or_default();
+    let with_preview = is_fzf_family(cmd) && paths.iter().any(|p| preview(p).is_some());
+    let group_labels = settings.group_labels.unwrap_or(false);
+
+    let lossy = settings.lossy_paths.unwrap_or(false);
+    let mut branches = HashMap::new();
+    let entries = paths
+        .iter()
+        .filter_map(|p| {
+            if lossy {
+                Some((p, p.to_string_lossy()))
+            } else {
+                p.to_str().map(|s| (p, s.into()))
+            }
+        })
+        .map(|(p, s)| {
+            let s = s.as_ref();
+            let mut line = display_line(settings, s, &mut branches);
+            if group_labels && let Some(group) = root(p).and_then(|r| group_prefix(r.path(), p)) {
+                line = format!("[{group}] {line}");
+            }
+
+            if with_preview {
+                let command = preview(p).map(|c| preview_command(c, s));
+                (format!("{}\t{line}", command.unwrap_or_default()), p)
+            } else {
+                (line, p)
+            }
+        })
+        .collect::<Vec<_>>();
+
+    let header = settings
+        .picker_header
+        .as_deref()
+        .map(|h| header_text(h, entries.len(), options.profile));
+    // Paths that aren't UTF-8 were dropped, so the index of a path and its entry can differ
+    let select = options
+        .select
+        .and_then(|i| paths.get(i))
+        .and_then(|selected| entries.iter().position(|(_, p)| *p == selected));
+    let options = PickerOptions {
+        preview: with_preview,
+        header: header.as_deref(),
+        select,
+        ..*options
+    };
+    let selected = run_picker(settings, &options, entries.iter().map(|(line, _)| line))?;
+
+    // Map the displayed lines back to the full paths
+    Ok(selected
+        .iter()
+        .flat_map(|s| s.split(delimiter(settings)))
+        .filter(|s| !s.is_empty())
+        .map(|s| {
+            entries
+                .iter()
+                .find(|(line, _)| *line == s)
+                .map(|(_, p)| p.to_path_buf())
+                .unwrap_or_else(|| PathBuf::from(s))
+        })
+        .collect())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn query_arg_test() {
+        let settings = Settings::default();
+        let options = PickerOptions {
+            query: Some("api"),
+            ..Default::default()
+        };
+
+        assert_eq!(
+            picker_command(&split_command("fzf-tmux -p 50%"), &settings, &options),
+            ("fzf-tmux", vec!["-p", "50%", "--query", "api"])
+        );
+        assert_eq!(
+            picker_command(&split_command("/usr/bin/fzf"), &settings, &options),
+            ("/usr/bin/fzf", vec!["--query", "api"])
+        );
+        assert_eq!(
+            picker_command(&split_command("sk"), &settings, &options),
+            ("sk", vec![])
+        );
+        assert_eq!(
+            picker_command(&split_command("fzf"), &settings, &PickerOptions::default()),
+            ("fzf", vec![])
+        );
+    }
+
+    #[test]
+    fn multi_arg_test() {
+        let options = PickerOptions {
+            multi: true,
+            ..Default::default()
+        };
+
+        assert_eq!(
+            picker_command(&split_command("fzf"), &Settings::default(), &options),
+            ("fzf", vec!["--multi"])
+        );
+        assert_eq!(
+            picker_command(&split_command("sk"), &Settings::default(), &options),
+            ("sk", vec![])
+        );
+    }
+
+    #[test]
+    fn header_arg_test() {
+        let header = header_text("{count} projects {profile}", 12, None);
+        let options = PickerOptions {
+            header: Some(&header),
+            ..Default::default()
+        };
+
+        assert_eq!(
+            picker_command(&split_command("fzf"), &Settings::default(), &options),
+            ("fzf", vec!["--header", "12 projects "])
+        );
+        assert_eq!(header_text("[{profile}]", 0, Some("work")), "[work]");
+        assert_eq!(
+            picker_command(&split_command("sk"), &Settings::default(), &options),
+            ("sk", vec![])
+        );
+    }
+
+    #[test]
+    fn exact_arg_test() {
+        let settings = Settings {
+            picker_exact: Some(true),
+            ..Default::default()
+        };
+        let options = PickerOptions::default();
+
+        assert_eq!(
+            picker_command(&split_command("fzf"), &settings, &options),
+            ("fzf", vec!["--exact"])
+        );
+        assert_eq!(
+            picker_command(&split_command("sk"), &settings, &options),
+            ("sk", vec![])
+        );
+        assert_eq!(
+            picker_command(&split_command("fzf"), &Settings::default(), &options),
+            ("fzf", vec![])
+        );
+    }
+
+    #[test]
+    fn extra_args_test() {
+        let settings = Settings {
+            picker_extra_args: Some(vec![
+                "--layout".into(),
+                "reverse".into(),
+                "--height=40%".into(),
+            ]),
+            ..Default::default()
+        };
+
+        assert_eq!(
+            picker_command(
+                &split_command("sk --ansi"),
+                &settings,
+                &PickerOptions::default()
+            ),
+            ("sk", vec!["--ansi", "--layout", "reverse", "--height=40%"])
+        );
+    }
+
+    #[test]
+    fn missing_picker_test() {
+        let settings = Settings {
+            picker: Some("tms-missing-picker --flag".into()),
+            ..Default::default()
+        };
+
+        assert!(matches!(
+            run_finder(&settings, &PickerOptions::default(), &[], &[]),
+            Err(Error::PickerNotFound(cmd)) if cmd == "tms-missing-picker"
+        ));
+    }
+
+    #[test]
+    fn picker_spawn_test() {
+        let dir = tempfile::tempdir().unwrap();
+        let picker = dir.path().join("picker");
+        std::fs::write(&picker, "#!/bin/sh\n").unwrap();
+        let settings = Settings {
+            picker: Some(picker.display().to_string()),
+            ..Default::default()
+        };
+
+        // Not executable
+        assert!(matches!(
+            run_finder(&settings, &PickerOptions::default(), &[], &[]),
+            Err(Error::PickerSpawn(e)) if e.contains("ermission denied")
+        ));
+    }
+
+    #[test]
+    fn absolute_picker_path_test() {
+        use std::os::unix::fs::PermissionsExt;
+
+        let dir = tempfile::tempdir().unwrap();
+        let bin = dir.path().join("Picker Tools");
+        std::fs::create_dir(&bin).unwrap();
+        let fzf = bin.join("fzf");
+        std::fs::write(&fzf, "#!/bin/sh\nhead -n1\n").unwrap();
+        std::fs::set_permissions(&fzf, std::fs::Permissions::from_mode(0o755)).unwrap();
+
+        let command = format!("'{}' --reverse", fzf.display());
+        assert_eq!(
+            picker_command(
+                &split_command(&command),
+                &Settings::default(),
+                &Default::default()
+            ),
+            (fzf.to_str().unwrap(), vec!["--reverse"])
+        );
+
+        let settings = Settings {
+            picker: Some(command),
+            ..Default::default()
+        };
+        let paths = [PathBuf::from("/code/api"), PathBuf::from("/code/web")];
+        assert_eq!(
+            run_finder(&settings, &PickerOptions::default(), &paths, &[]).unwrap(),
+            [PathBuf::from("/code/api")]
+        );
+    }
+
+    #[test]
+    fn select_after_dropped_path_test() {
+        use std::os::unix::{ffi::OsStrExt, fs::PermissionsExt};
+
+        // Prints the entry `--bind load:pos(N)` starts on
+        let dir = tempfile::tempdir().unwrap();
+        let fzf = dir.path().join("fzf");
+        std::fs::write(
+            &fzf,
+            "#!/bin/sh\nfor a; do case $a in load:pos*) n=${a#*(}; n=${n%)};; esac; done\nsed -n ${n}p\n",
+        )
+        .unwrap();
+        std::fs::set_permissions(&fzf, std::fs::Permissions::from_mode(0o755)).unwrap();
+
+        let settings = Settings {
+            picker: Some(fzf.display().to_string()),
+            ..Default::default()
+        };
+        let paths = [
+            PathBuf::from(std::ffi::OsStr::from_bytes(b"/code/caf\xe9")),
+            PathBuf::from("/code/api"),
+            PathBuf::from("/code/web"),
+        ];
+        let options = PickerOptions {
+            select: Some(2),
+            ..Default::default()
+        };
+
+        assert_eq!(
+            run_finder(&settings, &options, &paths, &[]).unwrap(),
+            [PathBuf::from("/code/web")]
+        );
+    }
+
+    #[test]
+    fn lossy_paths_test() {
+        use std::os::unix::ffi::OsStrExt;
+
+        let path = PathBuf::from(std::ffi::OsStr::from_bytes(b"/code/caf\xe9"));
+        let settings = Settings {
+            picker: Some("head -n1".into()),
+            ..Default::default()
+        };
+        let paths = [path.clone()];
+        let options = PickerOptions::default();
+
+        assert!(
+            run_finder(&settings, &options, &paths, &[])
+                .unwrap()
+                .is_empty()
+        );
+
+        let settings = Settings {
+            lossy_paths: Some(true),
+            ..settings
+        };
+        assert_eq!(
+            run_finder(&settings, &options, &paths, &[]).unwrap(),
+            [path]
+        );
+    }
+
+    #[test]
+    fn picker_tmux_mode_test() {
+        assert_eq!(default_picker(None), "fzf-tmux -p 50%");
+        assert_eq!(default_picker(Some("popup")), "fzf-tmux -p 50%");
+        assert_eq!(default_picker(Some("split-down")), "fzf-tmux -d 40%");
+        assert_eq!(default_picker(Some("split-right")), "fzf-tmux -r 40%");
+        assert_eq!(default_picker(Some("sideways")), "fzf-tmux -p 50%");
+
+        let settings = Settings {
+            picker: Some("sk".into()),
+            picker_tmux_mode: Some("split-down".into()),
+            ..Default::default()
+        };
+        assert_eq!(picker(&settings), "sk");
+    }
+
+    #[test]
+    fn icon_prefix_test() {
+        assert_eq!(
+            with_icon("/code/api", true),
+            format!("{GIT_ICON} /code/api")
+        );
+        assert_eq!(
+            with_icon("/code/notes", false),
+            format!("{FOLDER_ICON} /code/notes")
+        );
+    }
+
+    #[test]
+    fn preview_command_test() {
+        let roots = [
+            SearchPath::complex("/code").with_preview("git -C {path} status"),
+            SearchPath::complex("/code/vault").with_preview("tree {path}"),
+            SearchPath::Simple("/notes".into()),
+        ];
+        let preview = |path: &str| {
+            source_root(&roots, Path::new(path))
+                .and_then(|i| roots[i].preview())
+                .map(|c| preview_command(c, path))
+        };
+
+        assert_eq!(
+            preview("/code/api").as_deref(),
+            Some("git -C '/code/api' status")
+        );
+        assert_eq!(
+            preview("/code/vault/it's").as_deref(),
+            Some(r"tree '/code/vault/it'\''s'")
+        );
+        assert_eq!(preview("/notes/todo"), None);
+        assert_eq!(preview("/elsewhere"), None);
+    }
+
+    #[test]
+    fn large_input_test() {
+        let settings = Settings {
+            picker: Some("cat".into()),
+            ..Default::default()
+        };
+        let lines = (0..200_000).map(|i| format!("/code/project-{i}"));
+
+        let selected = run_picker(&settings, &PickerOptions::default(), lines).unwrap();
+        assert_eq!(selected.unwrap().lines().count(), 200_000);
+    }
+
+    #[test]
+    fn group_prefix_test() {
+        let root = Path::new("/code");
+
+        assert_eq!(
+            group_prefix(root, Path::new("/code/work/api")),
+            Some("work")
+        );
+        assert_eq!(group_prefix(root, Path::new("/code/work")), Some("work"));
+        assert_eq!(group_prefix(root, Path::new("/code")), None);
+        assert_eq!(group_prefix(root, Path::new("/notes/todo")), None);
+    }
+
+    #[test]
+    fn null_delimited_test() {
+        let settings = Settings {
+            picker_null_delimited: Some(true),
+            ..Default::default()
+        };
+        let paths = ["/code/two\nlines", "/code/api"];
+
+        let input = join_entries(paths, delimiter(&settings));
+        assert_eq!(input, "/code/two\nlines\0/code/api\0");
+
+        // fzf --print0 prints the selected entry followed by NUL
+        let selected = input.split_inclusive('\0').next().unwrap();
+        assert_eq!(
+            strip_delimiter(selected, delimiter(&settings)),
+            "/code/two\nlines"
+        );
+
+        assert_eq!(
+            picker_command(&split_command("fzf"), &settings, &PickerOptions::default()),
+            ("fzf", vec!["--read0", "--print0"])
+        );
+    }
+
+    #[test]
+    fn without_fzf_tmux_test() {
+        assert_eq!(without_fzf_tmux("fzf-tmux -p 50%").as_deref(), Some("fzf"));
+        assert_eq!(
+            without_fzf_tmux("/usr/bin/fzf-tmux -p80%,40% --reverse -w 100 -- --ansi").as_deref(),
+            Some("/usr/bin/fzf --reverse --ansi")
+        );
+        assert_eq!(
+            without_fzf_tmux("/opt/fzf/bin/fzf-tmux -p").as_deref(),
+            Some("/opt/fzf/bin/fzf")
+        );
+        assert_eq!(
+            without_fzf_tmux("fzf-tmux -d --height 40%").as_deref(),
+            Some("fzf --height 40%")
+        );
+        assert_eq!(
+            without_fzf_tmux("fzf-tmux -p --prompt 'pick > '").as_deref(),
+            Some("fzf --prompt 'pick > '")
+        );
+        assert_eq!(without_fzf_tmux("fzf --reverse"), None);
+        assert_eq!(without_fzf_tmux("sk"), None);
+    }
+}
@@ -1,35 +1,822 @@
-use std::process::{Command, Output as ProcessOutput};
+use crate::{
+    config::{LayoutWindow, Settings},
+    error::Error,
+};
+use std::{
+    ffi::OsStr,
+    path::Path,
+    process::{Command, Output as ProcessOutput},
+};
 
-fn cmd(args: &[&str]) -> Option<ProcessOutput> {
-    let output = Command::new("tmux")
-        .args(args)
-        .output()
-        .expect("Failed to run tmux command");
+/// Runs the processes for [`Tmux`], replaced in tests to record the commands.
+/// Arguments are `OsStr`s so paths that aren't UTF-8 reach tmux unchanged
+pub trait CommandRunner {
+    /// Runs `program` to completion, capturing its output
+    fn output(&self, program: &str, args: &[&OsStr]) -> std::io::Result<ProcessOutput>;
 
-    if output.status.success() {
-        Some(output)
-    } else {
-        None
+    /// Runs `program` in the current terminal, waiting for it to exit only if `wait` is set
+    fn spawn(&self, program: &str, args: &[&OsStr], wait: bool) -> std::io::Result<()>;
+}
+
+pub struct SystemRunner;
+
+impl CommandRunner for SystemRunner {
+    fn output(&self, program: &str, args: &[&OsStr]) -> std::io::Result<ProcessOutput> {
+        Command::new(program).args(args).output()
+    }
+
+    fn spawn(&self, program: &str, args: &[&OsStr], wait: bool) -> std::io::Result<()> {
+        let mut process = Command::new(program);
+        process.args(args);
+
+        if wait {
+            process.spawn()?.wait()?;
+        } else {
+            // A new terminal window outlives us, it gets reparented once we exit
+            #[allow(clippy::zombie_processes)]
+            process.spawn()?;
+        }
+
+        Ok(())
+    }
+}
+
+#[derive(Debug, PartialEq, Eq)]
+pub struct Session {
+    pub name: String,
+    /// Unix time of the last activity
+    pub activity: u64,
+    pub path: String,
+}
+
+const SESSION_FORMAT: &str = "#{session_name}\t#{session_activity}\t#{session_path}";
+
+/// Parses `list-sessions` output in [`SESSION_FORMAT`], most recently active first
+fn parse_sessions(output: &str) -> Vec<Session> {
+    let mut sessions = output
+        .lines()
+        .filter_map(|line| {
+            let mut fields = line.splitn(3, '\t');
+            Some(Session {
+                name: fields.next()?.to_string(),
+                activity: fields.next()?.parse().ok()?,
+                path: fields.next()?.to_string(),
+            })
+        })
+        .collect::<Vec<_>>();
+
+    sessions.sort_by_key(|s| std::cmp::Reverse(s.activity));
+    sessions
+}
+
+/// `env` holds `KEY=VALUE` pairs
+fn new_session_args<'a>(
+    name: &'a str,
+    path: &'a Path,
+    window_name: Option<&'a str>,
+    env: &'a [String],
+    shell: Option<&'a str>,
+) -> Vec<&'a OsStr> {
+    let mut args = vec![
+        "new-session".as_ref(),
+        "-c".as_ref(),
+        path.as_os_str(),
+        "-s".as_ref(),
+        name.as_ref(),
+        "-d".as_ref(),
+    ];
+    if let Some(window_name) = window_name {
+        args.extend(["-n", window_name].map(OsStr::new));
+    }
+    for var in env {
+        args.extend(["-e", var].map(OsStr::new));
+    }
+    if let Some(shell) = shell {
+        args.push(shell.as_ref());
     }
+
+    args
+}
+
+fn current_window_args<'a>(name: &'a str, path: &'a Path) -> [&'a OsStr; 5] {
+    [
+        "new-window".as_ref(),
+        "-c".as_ref(),
+        path.as_os_str(),
+        "-n".as_ref(),
+        name.as_ref(),
+    ]
+}
+
+fn list_clients_args(name: &str) -> [&str; 5] {
+    ["list-clients", "-t", name, "-F", "#{client_name}"]
+}
+
+fn popup_args<'a>(path: &'a Path, command: &'a str) -> [&'a OsStr; 5] {
+    [
+        "display-popup".as_ref(),
+        "-d".as_ref(),
+        path.as_os_str(),
+        "-E".as_ref(),
+        command.as_ref(),
+    ]
+}
+
+/// `tmux attach`, optionally wrapped in a terminal command like `alacritty -e`
+fn attach_command(
+    name: &str,
+    terminal: Option<&str>,
+    global_args: &[String],
+) -> Result<Vec<String>, Error> {
+    let mut command = match terminal {
+        Some(t) => shell_words::split(t)
+            .map_err(|e| Error::file_error(format!("settings.terminal '{t}': {e}")))?,
+        None => vec![],
+    };
+    command.push("tmux".into());
+    command.extend(global_args.iter().cloned());
+    command.extend(["attach".into(), "-t".into(), name.into()]);
+
+    Ok(command)
+}
+
+/// `program` and `args` as they'd be typed in a shell, quoting arguments the shell would change.
+/// Only for showing, arguments that aren't UTF-8 are shown lossily
+fn command_line(program: &str, args: &[&OsStr]) -> String {
+    shell_words::join(
+        std::iter::once(program.into()).chain(args.iter().map(|arg| arg.to_string_lossy())),
+    )
 }
 
-pub fn has_session(name: &str) -> bool {
-    cmd(&["has-session", "-t", name]).is_some()
+/// Why a tmux command failed
+enum CmdError {
+    /// tmux couldn't be started
+    Run(Error),
+    /// tmux exited with an error, holding its stderr
+    Failed(String),
 }
 
-pub fn new_session(name: &str, path: &str) {
-    cmd(&["new-session", "-c", path, "-s", name, "-d"]);
+/// A missing `tmux` gets its own error, so the user knows to install it
+fn run_error(e: std::io::Error) -> Error {
+    match e.kind() {
+        std::io::ErrorKind::NotFound => Error::TmuxNotFound,
+        _ => Error::RunTmux(e.to_string()),
+    }
+}
+
+/// Runs tmux against the server selected by the settings
+pub struct Tmux<R: CommandRunner = SystemRunner> {
+    runner: R,
+    /// Passed before every tmux subcommand
+    global_args: Vec<String>,
+    /// Print every command to stderr before running it
+    show_commands: bool,
+}
+
+impl Tmux {
+    pub fn new(settings: &Settings) -> Self {
+        Self::with_runner(settings, SystemRunner)
+    }
 }
 
-pub fn attach(name: &str) {
-    Command::new("tmux")
-        .args(["attach", "-t", name])
-        .spawn()
-        .expect("Failed to spawn tmux command")
-        .wait()
-        .expect("Failed to attach to tmux session");
+impl<R: CommandRunner> Tmux<R> {
+    pub fn with_runner(settings: &Settings, runner: R) -> Self {
+        let mut global_args = vec![];
+        if let Some(socket) = &settings.tmux_socket {
+            global_args.extend(["-L".to_string(), socket.clone()]);
+        }
+        if let Some(socket_path) = &settings.tmux_socket_path {
+            global_args.extend(["-S".to_string(), socket_path.clone()]);
+        }
+        if let Some(config) = &settings.tmux_config {
+            global_args.extend(["-f".to_string(), config.clone()]);
+        }
+
+        Self {
+            runner,
+            global_args,
+            show_commands: false,
+        }
+    }
+
+    pub fn show_commands(self, show_commands: bool) -> Self {
+        Self {
+            show_commands,
+            ..self
+        }
+    }
+
+    fn echo(&self, program: &str, args: &[&OsStr]) {
+        let line = command_line(program, args);
+        if self.show_commands {
+            eprintln!("{line}");
+        }
+        log::debug!("Running {line}");
+    }
+
+    fn cmd<S: AsRef<OsStr>>(&self, args: &[S]) -> Option<ProcessOutput> {
+        self.try_cmd(args).ok()
+    }
+
+    /// Runs a tmux subcommand
+    fn try_cmd<S: AsRef<OsStr>>(&self, args: &[S]) -> Result<ProcessOutput, CmdError> {
+        let mut full_args = self.global_args.iter().map(OsStr::new).collect::<Vec<_>>();
+        full_args.extend(args.iter().map(AsRef::as_ref));
+
+        self.echo("tmux", &full_args);
+        let output = self
+            .runner
+            .output("tmux", &full_args)
+            .map_err(|e| CmdError::Run(run_error(e)))?;
+
+        if output.status.success() {
+            Ok(output)
+        } else {
+            let stderr = String::from_utf8_lossy(&output.stderr);
+            Err(CmdError::Failed(stderr.trim().to_string()))
+        }
+    }
+
+    /// The running sessions, none when there's no tmux server. Fails if tmux can't be run
+    pub fn list_sessions(&self) -> Result<Vec<Session>, Error> {
+        match self.try_cmd(&["list-sessions", "-F", SESSION_FORMAT]) {
+            Ok(output) => Ok(parse_sessions(&String::from_utf8_lossy(&output.stdout))),
+            Err(CmdError::Failed(_)) => Ok(vec![]),
+            Err(CmdError::Run(e)) => Err(e),
+        }
+    }
+
+    /// Name of the session the current client is attached to
+    pub fn current_session(&self) -> Option<String> {
+        let output = self.cmd(&["display-message", "-p", "#{session_name}"])?;
+        let name = String::from_utf8_lossy(&output.stdout)
+            .trim_end()
+            .to_string();
+
+        Some(name).filter(|n| !n.is_empty())
+    }
+
+    pub fn rename_session(&self, old: &str, new: &str) {
+        self.cmd(&["rename-session", "-t", old, new]);
+    }
+
+    pub fn has_session(&self, name: &str) -> bool {
+        self.cmd(&["has-session", "-t", name]).is_some()
+    }
+
+    pub fn kill_session(&self, name: &str) {
+        self.cmd(&["kill-session", "-t", name]);
+    }
+
+    /// Whether any client is attached to session `name`
+    pub fn session_attached(&self, name: &str) -> bool {
+        self.cmd(&list_clients_args(name))
+            .is_some_and(|output| !String::from_utf8_lossy(&output.stdout).trim().is_empty())
+    }
+
+    /// Creates session `name`, returning false when another client created it first
+    pub fn new_session(
+        &self,
+        name: &str,
+        path: &Path,
+        window_name: Option<&str>,
+        env: &[String],
+        shell: Option<&str>,
+    ) -> Result<bool, Error> {
+        match self.try_cmd(&new_session_args(name, path, window_name, env, shell)) {
+            Ok(_) => Ok(true),
+            Err(CmdError::Failed(e)) if e.starts_with("duplicate session") => Ok(false),
+            Err(CmdError::Failed(e)) => Err(Error::NewSession(format!("'{name}': {e}"))),
+            Err(CmdError::Run(e)) => Err(e),
+        }
+    }
+
+    /// Creates session `name` in `path` unless it's already running, returning whether it was created
+    pub fn ensure_session(&self, name: &str, path: &Path) -> Result<bool, Error> {
+        if self.has_session(name) {
+            return Ok(false);
+        }
+
+        self.new_session(name, path, None, &[], None)
+    }
+
+    /// Creates session `name` with `windows`, the first one through `shell`, and types each
+    /// window's command into it. A session someone else just created is left as it is, returns
+    /// whether the session was created
+    pub fn new_session_with_windows(
+        &self,
+        name: &str,
+        path: &Path,
+        windows: &[LayoutWindow],
+        env: &[String],
+        shell: Option<&str>,
+    ) -> Result<bool, Error> {
+        let (first, rest) = match windows.split_first() {
+            Some((first, rest)) => (Some(first), rest),
+            None => (None, windows),
+        };
+
+        if !self.new_session(name, path, first.map(|w| w.name.as_str()), env, shell)? {
+            return Ok(false);
+        }
+        if let Some(command) = first.and_then(|w| w.command.as_deref()) {
+            self.send_keys(name, command);
+        }
+
+        for window in rest {
+            self.new_window(name, &window.name, path);
+            if let Some(command) = &window.command {
+                self.send_keys(&format!("{name}:{}", window.name), command);
+            }
+        }
+
+        Ok(true)
+    }
+
+    /// Runs `set-option -t <session>` with `option` split like a shell would, e.g.
+    /// `destroy-unattached off` or `status-left "#S | #H"`
+    pub fn set_option(&self, session: &str, option: &str) {
+        let words = match shell_words::split(option) {
+            Ok(words) => words,
+            Err(e) => {
+                log::warn!("skipping session option '{option}': {e}");
+                return;
+            }
+        };
+        let mut args = vec!["set-option", "-t", session];
+        args.extend(words.iter().map(String::as_str));
+        self.cmd(&args);
+    }
+
+    /// Adds a window to `session` without switching to it
+    pub fn new_window(&self, session: &str, name: &str, path: &Path) {
+        let target = format!("{session}:");
+        let args = ["new-window", "-d", "-t", &target, "-n", name, "-c"].map(OsStr::new);
+        self.cmd(&[&args[..], &[path.as_os_str()]].concat());
+    }
+
+    pub fn send_keys(&self, name: &str, keys: impl AsRef<OsStr>) {
+        self.cmd(&[
+            "send-keys".as_ref(),
+            "-t".as_ref(),
+            name.as_ref(),
+            keys.as_ref(),
+            "Enter".as_ref(),
+        ]);
+    }
+
+    pub fn attach(&self, name: &str, terminal: Option<&str>) -> Result<(), Error> {
+        let command = attach_command(name, terminal, &self.global_args)?;
+        let args = command[1..].iter().map(OsStr::new).collect::<Vec<_>>();
+        self.echo(&command[0], &args);
+
+        self.runner
+            .spawn(&command[0], &args, terminal.is_none())
+            .map_err(|e| match terminal {
+                // A missing terminal isn't a missing tmux
+                Some(t) => Error::RunTmux(format!("'{t}': {e}")),
+                None => run_error(e),
+            })
+    }
+
+    pub fn switch(&self, name: &str) {
+        self.cmd(&["switch", "-t", name]);
+    }
+
+    /// Opens and switches to a new window in the current session
+    pub fn new_current_window(&self, name: &str, path: &Path) {
+        self.cmd(&current_window_args(name, path));
+    }
+
+    /// Runs `command` in a popup over the current client, closing it when the command exits
+    pub fn popup(&self, path: &Path, command: &str) {
+        self.cmd(&popup_args(path, command));
+    }
+
+    /// Opens a window in the current session running a nested client attached to `name`
+    pub fn attach_in_window(&self, name: &str) {
+        let attach = attach_command(name, None, &self.global_args).unwrap_or_default();
+        // tmux refuses to nest clients unless TMUX is unset
+        let command = format!("TMUX= {}", shell_words::join(attach));
+
+        self.cmd(&["new-window", "-n", name, &command]);
+    }
 }
 
-pub fn switch(name: &str) {
-    cmd(&["switch", "-t", name]);
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::{cell::RefCell, os::unix::process::ExitStatusExt, process::ExitStatus};
+
+    /// Records every command instead of running it
+    #[derive(Default)]
+    struct RecordingRunner {
+        commands: RefCell<Vec<Vec<String>>>,
+        /// This tmux subcommand exits with an error
+        failing: Option<&'static str>,
+        /// Written to stderr by the failing subcommand
+        stderr: &'static str,
+    }
+
+    impl RecordingRunner {
+        fn record(&self, program: &str, args: &[&OsStr]) {
+            let args = args.iter().map(|a| a.to_string_lossy().into_owned());
+            self.commands
+                .borrow_mut()
+                .push(std::iter::once(program.to_string()).chain(args).collect());
+        }
+    }
+
+    impl CommandRunner for &RecordingRunner {
+        fn output(&self, program: &str, args: &[&OsStr]) -> std::io::Result<ProcessOutput> {
+            self.record(program, args);
+            let failed = self.failing.is_some_and(|f| args.contains(&OsStr::new(f)));
+            Ok(ProcessOutput {
+                status: ExitStatus::from_raw(if failed { 1 << 8 } else { 0 }),
+                stdout: vec![],
+                stderr: if failed { self.stderr.into() } else { vec![] },
+            })
+        }
+
+        fn spawn(&self, program: &str, args: &[&OsStr], _wait: bool) -> std::io::Result<()> {
+            self.record(program, args);
+            Ok(())
+        }
+    }
+
+    #[test]
+    fn new_session_args_test() {
+        assert_eq!(
+            new_session_args("api", Path::new("/code/api"), None, &[], None),
+            ["new-session", "-c", "/code/api", "-s", "api", "-d"]
+        );
+        assert_eq!(
+            new_session_args(
+                "api",
+                Path::new("/code/api"),
+                Some("editor"),
+                &["AWS_PROFILE=work".into()],
+                Some("zsh")
+            ),
+            [
+                "new-session",
+                "-c",
+                "/code/api",
+                "-s",
+                "api",
+                "-d",
+                "-n",
+                "editor",
+                "-e",
+                "AWS_PROFILE=work",
+                "zsh"
+            ]
+        );
+    }
+
+    #[test]
+    fn attach_command_test() {
+        assert_eq!(
+            attach_command("api", None, &[]).unwrap(),
+            ["tmux", "attach", "-t", "api"]
+        );
+        assert_eq!(
+            attach_command("api", Some("alacritty -e"), &[]).unwrap(),
+            ["alacritty", "-e", "tmux", "attach", "-t", "api"]
+        );
+        assert_eq!(
+            attach_command("api", Some(r#"alacritty  -e "sh -c" "#), &[]).unwrap(),
+            ["alacritty", "-e", "sh -c", "tmux", "attach", "-t", "api"]
+        );
+        assert!(matches!(
+            attach_command("api", Some("alacritty -e 'sh"), &[]),
+            Err(Error::FileError(_))
+        ));
+    }
+
+    #[test]
+    fn command_line_test() {
+        assert_eq!(
+            command_line(
+                "tmux",
+                &["new-session", "-c", "/code/api", "-s", "api"].map(OsStr::new)
+            ),
+            "tmux new-session -c /code/api -s api"
+        );
+        assert_eq!(
+            command_line(
+                "tmux",
+                &["send-keys", "-t", "api", "git status", "Enter"].map(OsStr::new)
+            ),
+            "tmux send-keys -t api 'git status' Enter"
+        );
+        assert_eq!(
+            command_line("tmux", &["new-window", "-n", "it's", ""].map(OsStr::new)),
+            r"tmux new-window -n 'it'\''s' ''"
+        );
+    }
+
+    #[test]
+    fn attach_in_window_quoting_test() {
+        let runner = RecordingRunner::default();
+        Tmux::with_runner(&Settings::default(), &runner).attach_in_window("it's; rm -rf ~");
+
+        let command = runner.commands.into_inner().remove(0);
+        assert_eq!(
+            command.last().unwrap(),
+            r"TMUX= tmux attach -t 'it'\''s; rm -rf ~'"
+        );
+        assert_eq!(
+            shell_words::split(command.last().unwrap()).unwrap(),
+            ["TMUX=", "tmux", "attach", "-t", "it's; rm -rf ~"]
+        );
+    }
+
+    #[test]
+    fn current_window_args_test() {
+        assert_eq!(
+            current_window_args("web", Path::new("/code/mono/web")),
+            ["new-window", "-c", "/code/mono/web", "-n", "web"]
+        );
+    }
+
+    #[test]
+    fn list_clients_args_test() {
+        assert_eq!(
+            list_clients_args("api"),
+            ["list-clients", "-t", "api", "-F", "#{client_name}"]
+        );
+    }
+
+    #[test]
+    fn popup_args_test() {
+        assert_eq!(
+            popup_args(Path::new("/code/api"), "zsh"),
+            ["display-popup", "-d", "/code/api", "-E", "zsh"]
+        );
+    }
+
+    #[test]
+    fn socket_args_test() {
+        let runner = RecordingRunner::default();
+        let settings = Settings {
+            tmux_socket: Some("work".into()),
+            tmux_socket_path: Some("/tmp/tmux.sock".into()),
+            ..Default::default()
+        };
+        let tmux = Tmux::with_runner(&settings, &runner);
+
+        tmux.has_session("api");
+        tmux.new_session("api", Path::new("/code/api"), None, &[], None)
+            .unwrap();
+        tmux.attach("api", None).unwrap();
+        tmux.switch("api");
+        tmux.attach_in_window("api");
+        tmux.rename_session("api", "api_v2");
+
+        let commands = runner.commands.into_inner();
+        assert_eq!(
+            commands[4][5..],
+            [
+                "new-window",
+                "-n",
+                "api",
+                "TMUX= tmux -L work -S /tmp/tmux.sock attach -t api"
+            ]
+        );
+        assert_eq!(commands[5][5..], ["rename-session", "-t", "api", "api_v2"]);
+        assert_eq!(commands.len(), 6);
+        for command in commands {
+            assert_eq!(command[..5], ["tmux", "-L", "work", "-S", "/tmp/tmux.sock"]);
+        }
+    }
+
+    #[test]
+    fn ensure_session_test() {
+        let settings = Settings::default();
+
+        let runner = RecordingRunner {
+            failing: Some("has-session"),
+            ..Default::default()
+        };
+        assert!(
+            Tmux::with_runner(&settings, &runner)
+                .ensure_session("scratch", Path::new("/home/me"))
+                .unwrap()
+        );
+        assert_eq!(
+            runner.commands.into_inner()[1],
+            [
+                "tmux",
+                "new-session",
+                "-c",
+                "/home/me",
+                "-s",
+                "scratch",
+                "-d"
+            ]
+        );
+
+        let runner = RecordingRunner::default();
+        assert!(
+            !Tmux::with_runner(&settings, &runner)
+                .ensure_session("scratch", Path::new("/home/me"))
+                .unwrap()
+        );
+        assert_eq!(runner.commands.into_inner().len(), 1);
+    }
+
+    #[test]
+    fn config_file_test() {
+        let runner = RecordingRunner::default();
+        let settings = Settings {
+            tmux_config: Some("demo.conf".into()),
+            ..Default::default()
+        };
+        let tmux = Tmux::with_runner(&settings, &runner);
+
+        tmux.has_session("api");
+        tmux.list_sessions().unwrap();
+        tmux.send_keys("api", "ls");
+        tmux.attach("api", Some("alacritty -e")).unwrap();
+
+        let commands = runner.commands.into_inner();
+        for command in &commands[..3] {
+            assert_eq!(command[..3], ["tmux", "-f", "demo.conf"]);
+        }
+        assert_eq!(
+            commands[3][..5],
+            ["alacritty", "-e", "tmux", "-f", "demo.conf"]
+        );
+    }
+
+    #[test]
+    fn layout_windows_test() {
+        let window = |name: &str, command: Option<&str>| LayoutWindow {
+            name: name.into(),
+            command: command.map(str::to_string),
+        };
+        let runner = RecordingRunner::default();
+
+        Tmux::with_runner(&Settings::default(), &runner)
+            .new_session_with_windows(
+                "api",
+                Path::new("/code/api"),
+                &[
+                    window("editor", Some("nvim")),
+                    window("shell", None),
+                    window("server", Some("cargo run")),
+                ],
+                &[],
+                None,
+            )
+            .unwrap();
+
+        let commands = runner.commands.into_inner();
+        let commands = commands
+            .iter()
+            .map(|c| c[1..].join(" "))
+            .collect::<Vec<_>>();
+        assert_eq!(
+            commands,
+            [
+                "new-session -c /code/api -s api -d -n editor",
+                "send-keys -t api nvim Enter",
+                "new-window -d -t api: -n shell -c /code/api",
+                "new-window -d -t api: -n server -c /code/api",
+                "send-keys -t api:server cargo run Enter",
+            ]
+        );
+    }
+
+    #[test]
+    fn set_option_test() {
+        let settings = Settings::default();
+        let runner = RecordingRunner::default();
+        let tmux = Tmux::with_runner(&settings, &runner);
+
+        tmux.set_option("api", "destroy-unattached off");
+        tmux.set_option("api", r##"status-left "#S | #H""##);
+        tmux.set_option("api", "status-left 'unclosed");
+
+        assert_eq!(
+            runner.commands.borrow()[..],
+            [
+                [
+                    "tmux",
+                    "set-option",
+                    "-t",
+                    "api",
+                    "destroy-unattached",
+                    "off"
+                ],
+                ["tmux", "set-option", "-t", "api", "status-left", "#S | #H"],
+            ]
+        );
+    }
+
+    #[test]
+    fn parse_sessions_test() {
+        let output = "api\t1700000100\t/code/api\nnotes\t1700000300\t/home/me/notes\nbroken\n";
+
+        assert_eq!(
+            parse_sessions(output),
+            [
+                Session {
+                    name: "notes".into(),
+                    activity: 1700000300,
+                    path: "/home/me/notes".into(),
+                },
+                Session {
+                    name: "api".into(),
+                    activity: 1700000100,
+                    path: "/code/api".into(),
+                },
+            ]
+        );
+    }
+
+    #[test]
+    fn duplicate_session_test() {
+        let settings = Settings::default();
+        let runner = RecordingRunner {
+            failing: Some("new-session"),
+            stderr: "duplicate session: api\n",
+            ..Default::default()
+        };
+        let tmux = Tmux::with_runner(&settings, &runner);
+
+        tmux.new_session_with_windows(
+            "api",
+            Path::new("/code/api"),
+            &[
+                LayoutWindow {
+                    name: "editor".into(),
+                    command: Some("nvim".into()),
+                },
+                LayoutWindow {
+                    name: "shell".into(),
+                    command: None,
+                },
+            ],
+            &[],
+            None,
+        )
+        .unwrap();
+        assert_eq!(runner.commands.borrow().len(), 1);
+
+        let runner = RecordingRunner {
+            failing: Some("new-session"),
+            stderr: "bad session name\n",
+            ..Default::default()
+        };
+        assert!(matches!(
+            Tmux::with_runner(&settings, &runner).new_session("api", Path::new("/code/api"), None, &[], None),
+            Err(Error::NewSession(e)) if e == "'api': bad session name"
+        ));
+    }
+
+    #[test]
+    fn missing_tmux_test() {
+        struct MissingTmux;
+
+        impl CommandRunner for MissingTmux {
+            fn output(&self, program: &str, _args: &[&OsStr]) -> std::io::Result<ProcessOutput> {
+                Err(std::io::Error::new(
+                    std::io::ErrorKind::NotFound,
+                    format!("{program}: not found"),
+                ))
+            }
+
+            fn spawn(&self, program: &str, args: &[&OsStr], _wait: bool) -> std::io::Result<()> {
+                self.output(program, args).map(|_| ())
+            }
+        }
+
+        let tmux = Tmux::with_runner(&Settings::default(), MissingTmux);
+        assert!(matches!(tmux.list_sessions(), Err(Error::TmuxNotFound)));
+        assert!(matches!(
+            tmux.new_session("api", Path::new("/code/api"), None, &[], None),
+            Err(Error::TmuxNotFound)
+        ));
+        assert!(matches!(tmux.attach("api", None), Err(Error::TmuxNotFound)));
+        assert!(matches!(
+            tmux.attach("api", Some("alacritty -e")),
+            Err(Error::RunTmux(e)) if e.starts_with("'alacritty -e'")
+        ));
+        assert!(!tmux.has_session("api"));
+
+        // No server running is just no sessions
+        let runner = RecordingRunner {
+            failing: Some("list-sessions"),
+            stderr: "no server running on /tmp/tmux-1000/default\n",
+            ..Default::default()
+        };
+        assert!(
+            Tmux::with_runner(&Settings::default(), &runner)
+                .list_sessions()
+                .unwrap()
+                .is_empty()
+        );
+    }
 }